@@ -0,0 +1,134 @@
+use crate::types::Price;
+use std::fmt;
+
+/// Why an order was refused, or an operation on a resting order failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderError {
+    /// Orders must be for a positive quantity
+    NonPositiveQuantity,
+    /// Orders must be limited at a positive price
+    NonPositivePrice,
+    /// The price is not a multiple of the tick size
+    InvalidTick,
+    /// The quantity is not a multiple of the lot size, or is below the
+    /// minimum quantity
+    InvalidLotSize,
+    /// The quantity is above the book's maximum order quantity
+    QuantityAboveMax,
+    /// An order with the same id is already resting in the book, or waiting
+    /// as a stop order
+    DuplicateId,
+    /// No resting order has the given id
+    OrderNotFound,
+    /// The order would have traded against a resting order of its own
+    /// owner, and the self-trade policy cancels the incoming order
+    SelfTradePrevented,
+    /// A reduce-only order found no position left for it to reduce
+    NoPositionToReduce,
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::NonPositiveQuantity => write!(f, "order quantity must be positive"),
+            OrderError::NonPositivePrice => write!(f, "order price must be positive"),
+            OrderError::InvalidTick => write!(f, "order price is not a multiple of the tick size"),
+            OrderError::InvalidLotSize => write!(f, "order quantity is not a whole number of lots above the minimum"),
+            OrderError::QuantityAboveMax => write!(f, "order quantity is above the maximum order quantity"),
+            OrderError::DuplicateId => write!(f, "an order with this id is already resting or pending"),
+            OrderError::OrderNotFound => write!(f, "order not found"),
+            OrderError::SelfTradePrevented => write!(f, "order would trade against its own owner's resting order"),
+            OrderError::NoPositionToReduce => write!(f, "reduce-only order has no position to reduce"),
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+/// Why a resting order could not be split
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitError {
+    /// No resting order has the given id
+    OrderNotFound,
+    /// Both pieces must keep a positive quantity
+    InvalidQuantity,
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::OrderNotFound => write!(f, "order not found"),
+            SplitError::InvalidQuantity => write!(f, "split quantity must leave both pieces positive"),
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+/// Why a post-only order was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostOnlyRejected {
+    /// The order would have traded against the opposite side's best price
+    WouldCross { best_opposite: Price },
+    /// Orders must be for a positive quantity
+    NonPositiveQuantity,
+}
+
+impl fmt::Display for PostOnlyRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostOnlyRejected::WouldCross { best_opposite } => {
+                write!(f, "post-only order would cross the opposite best price {}", **best_opposite)
+            }
+            PostOnlyRejected::NonPositiveQuantity => write!(f, "order quantity must be positive"),
+        }
+    }
+}
+
+impl std::error::Error for PostOnlyRejected {}
+
+/// Broken matching invariant found by `OrderBook::place_order_checked`,
+/// with the index of the offending trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchAnomaly {
+    /// A trade executed no quantity
+    ZeroQuantity { trade: usize },
+    /// A trade executed at a price the incoming order's limit doesn't allow
+    PriceOutsideLimit { trade: usize, price: Price },
+    /// A trade has the same order on both sides
+    SelfMatch { trade: usize },
+}
+
+impl fmt::Display for MatchAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchAnomaly::ZeroQuantity { trade } => write!(f, "trade {trade} has zero quantity"),
+            MatchAnomaly::PriceOutsideLimit { trade, price } => {
+                write!(f, "trade {trade} executed at {} outside the order's limit", **price)
+            }
+            MatchAnomaly::SelfMatch { trade } => write!(f, "trade {trade} matched an order against itself"),
+        }
+    }
+}
+
+impl std::error::Error for MatchAnomaly {}
+
+/// Why a level snapshot was refused by `OrderBook::from_levels_strict`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportError {
+    /// Bids not strictly descending or asks not strictly ascending
+    Unsorted,
+    /// The best bid is at or above the best ask
+    Crossed,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Unsorted => write!(f, "levels are not in strict price order"),
+            ImportError::Crossed => write!(f, "bid and ask levels cross"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
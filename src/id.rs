@@ -0,0 +1,38 @@
+use std::fmt::Debug;
+use uuid::Uuid;
+
+/// Source of the ids given to new orders
+pub trait IdGenerator: Debug {
+    fn next_id(&mut self) -> Uuid;
+}
+
+/// Random version 4 ids
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RandomIdGenerator;
+
+impl IdGenerator for RandomIdGenerator {
+    fn next_id(&mut self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// Ids counting up from a starting value, for deterministic tests and
+/// replays. The id is the counter's value as a 128-bit integer.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SequentialIdGenerator {
+    next: u128,
+}
+
+impl SequentialIdGenerator {
+    pub const fn new(start: u128) -> Self {
+        SequentialIdGenerator { next: start }
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    fn next_id(&mut self) -> Uuid {
+        let id = Uuid::from_u128(self.next);
+        self.next += 1;
+        id
+    }
+}
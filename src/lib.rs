@@ -1,331 +1,5554 @@
+pub mod clock;
+pub mod error;
+pub mod id;
+pub mod listener;
+pub mod market;
 pub mod types;
 
-use std::collections::{BTreeMap, VecDeque};
-use types::{Order, Side, Trade, Price, Quantity};
+use clock::{Clock, SystemClock};
+use error::{ImportError, MatchAnomaly, OrderError, PostOnlyRejected, SplitError};
+use id::{IdGenerator, RandomIdGenerator};
+use listener::BookListener;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque, btree_map},
+    fmt::{self, Write},
+};
+use types::{Ack, BookEvent, BookSnapshot, BookStats, Command, LevelDiscipline, MarketData, Order, PlaceResult, Side, Trade, Price, Quantity, RemainderRestPolicy, SelfTradePolicy, SessionState, StopOrder, SublotRemainderPolicy, TradePricingPolicy};
 use uuid::Uuid;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct OrderBook {
     /// Bids: higher prices first (reverse order)
     bids: BTreeMap<Price, VecDeque<Order>>,
     /// Asks: lower prices first (natural order)
     asks: BTreeMap<Price, VecDeque<Order>>,
+    /// Price level of every resting order, lit or hidden, by id
+    order_index: HashMap<Uuid, Price>,
+    /// Emptied level queues kept around so new levels can reuse their
+    /// allocation
+    spare_levels: Vec<VecDeque<Order>>,
+    /// Stamps incoming orders with their entry time
+    clock: Box<dyn Clock>,
+    /// Gives new orders their ids
+    id_generator: Box<dyn IdGenerator>,
+    /// Notified of book changes, if any
+    listener: Option<Box<dyn BookListener>>,
+    /// Receives every mutation as a `BookEvent`, if set
+    event_handler: Option<EventHandler>,
+    /// Whether incoming orders match or just queue up for the auction
+    session_state: SessionState,
+    /// Price at which partially matched orders rest their remainder
+    remainder_rest_policy: RemainderRestPolicy,
+    /// Number of operations that changed the book, stamped on market data
+    sequence: u64,
+    /// Largest quantity a single order may carry, if limited
+    max_order_quantity: Option<i32>,
+    /// Most price levels kept per side, if limited
+    max_levels_per_side: Option<usize>,
+    /// Whether levels fill oldest or newest order first
+    level_discipline: LevelDiscipline,
+    /// Smallest quantity allowed to rest on each side
+    min_bid_quote_size: i32,
+    min_ask_quote_size: i32,
+    /// Whether a limit exactly at the opposite price is marketable
+    equal_price_crosses: bool,
+    /// Most trades a single incoming order may generate, if limited
+    max_trades_per_order: Option<usize>,
+    /// How incoming orders treat resting orders of their own owner
+    self_trade_policy: SelfTradePolicy,
+    /// Price crossing lit trades execute at
+    trade_pricing_policy: TradePricingPolicy,
+    /// Units of stored quantity per unit of quantity shown in market data
+    quantity_scale: i32,
+    /// Decimal places of the fixed-point prices, 0 for whole units
+    price_decimals: u32,
+    /// Prices of validated orders must be a multiple of this
+    tick_size: i64,
+    /// Quantities of validated orders must be a multiple of this
+    lot_size: i32,
+    /// Whether a traded order's odd lot rests with its remainder
+    sublot_remainder_policy: SublotRemainderPolicy,
+    /// Smallest quantity a validated order may have
+    min_quantity: i32,
+    /// Operations an emptied level stays published for before removal
+    level_retention: u64,
+    /// Emptied levels still in the book, with the last sequence number they
+    /// are kept for
+    retained_levels: Vec<(Side, Price, u64)>,
+    /// Invisible orders, matched at the lit midpoint before the lit book
+    hidden_bids: BTreeMap<Price, VecDeque<Order>>,
+    hidden_asks: BTreeMap<Price, VecDeque<Order>>,
+    /// Reference price for marking resting orders to market
+    mark_price: Option<Price>,
+    /// Stop orders waiting for their trigger, in arrival order
+    stop_orders: Vec<StopOrder>,
+    /// Most recent trades, oldest first, up to `trade_history_capacity`
+    trade_history: VecDeque<HistoryEntry>,
+    trade_history_capacity: usize,
+    /// Trades executed so far
+    trade_count: u64,
+    /// Quantity traded so far
+    traded_volume: u64,
+    /// Price of the most recent trade
+    last_trade_price: Option<Price>,
+    /// Net quantity each owner has bought minus sold, orders without an
+    /// owner left out
+    positions: HashMap<u64, i64>,
 }
 
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Levels shown on each side by the `Display` ladder
+const LADDER_LEVELS: usize = 10;
+
+/// Ladder of the top levels of each side: asks above the spread, bids below,
+/// highest price first throughout, with market data prices and quantities
+impl fmt::Display for OrderBook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_levels = |f: &mut fmt::Formatter<'_>, levels: &[(Price, Quantity)], empty: &str| {
+            if levels.is_empty() {
+                return writeln!(f, "{empty:>12}");
+            }
+            for &(price, quantity) in levels {
+                writeln!(f, "{:>12} {:>12}", self.display_price(price), self.display_quantity(quantity))?;
+            }
+            Ok(())
+        };
+
+        let mut asks: Levels = self.levels(Side::Sell).take(LADDER_LEVELS).collect();
+        asks.reverse();
+        let bids: Levels = self.levels(Side::Buy).take(LADDER_LEVELS).collect();
+
+        writeln!(f, "{:>12} {:>12}", "price", "quantity")?;
+        write_levels(f, &asks, "no asks")?;
+        let spread = self.spread().map(|spread| format!(" spread {} ", self.display_price(spread)));
+        writeln!(f, "{:-^25}", spread.unwrap_or_default())?;
+        write_levels(f, &bids, "no bids")
+    }
+}
+
+/// Walks the levels of one side in priority order, one at a time, skipping
+/// retained empty levels. Created by [`OrderBook::cursor`].
+#[derive(Debug, Clone)]
+pub struct LevelCursor<'a> {
+    side: Side,
+    levels: btree_map::Iter<'a, Price, VecDeque<Order>>,
+}
+
+impl LevelCursor<'_> {
+    /// Next level's price and aggregate quantity, `None` once the side is
+    /// exhausted
+    pub fn next_level(&mut self) -> Option<(Price, Quantity)> {
+        loop {
+            let (price, orders) = match self.side {
+                Side::Buy => self.levels.next_back()?,
+                Side::Sell => self.levels.next()?,
+            };
+            if !orders.is_empty() {
+                return Some((*price, aggregate_quantity_at_price(orders)));
+            }
+        }
+    }
+}
+
+/// Callback installed by `OrderBook::set_event_handler`
+struct EventHandler(Box<dyn FnMut(BookEvent)>);
+
+impl fmt::Debug for EventHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventHandler")
+    }
+}
+
+/// Passes the event built by `event` to `handler`, if there is one. The
+/// event is only built when someone receives it.
+fn emit(handler: &mut Option<EventHandler>, event: impl FnOnce() -> BookEvent) {
+    if let Some(EventHandler(handler)) = handler {
+        handler(event());
+    }
+}
+
+/// A trade kept in the trade history
+#[derive(Debug, Clone)]
+struct HistoryEntry {
+    /// Sequence number of the operation that produced the trade
+    sequence: u64,
+    trade: Trade,
+    /// Best bid and ask as the operation started, `None` if a side was empty
+    touch: Option<(Price, Price)>,
+}
+
+/// Aggregated price levels of one side, best first
+type Levels = Vec<(Price, Quantity)>;
+
+/// Capacity given to each level queue pre-allocated by `reserve`
+const RESERVED_LEVEL_CAPACITY: usize = 4;
+
+/// Most decimal places `with_price_decimals` accepts
+const MAX_PRICE_DECIMALS: u32 = 18;
+
+/// Most emptied level queues kept around for reuse
+const SPARE_LEVEL_LIMIT: usize = 32;
+
+/// Capacity above which an emptied level queue is shrunk before reuse, so
+/// the pool doesn't hold on to the peak size of a busy level
+const SPARE_LEVEL_MAX_CAPACITY: usize = 64;
+
 impl OrderBook {
     pub fn new() -> Self {
         OrderBook {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            order_index: HashMap::new(),
+            spare_levels: Vec::new(),
+            clock: Box::new(SystemClock),
+            id_generator: Box::new(RandomIdGenerator),
+            listener: None,
+            event_handler: None,
+            session_state: SessionState::Open,
+            remainder_rest_policy: RemainderRestPolicy::AtLimit,
+            sequence: 0,
+            max_order_quantity: None,
+            max_levels_per_side: None,
+            level_discipline: LevelDiscipline::Fifo,
+            min_bid_quote_size: 0,
+            min_ask_quote_size: 0,
+            equal_price_crosses: true,
+            max_trades_per_order: None,
+            self_trade_policy: SelfTradePolicy::Allow,
+            trade_pricing_policy: TradePricingPolicy::MakerPrice,
+            quantity_scale: 1,
+            price_decimals: 0,
+            tick_size: 1,
+            lot_size: 1,
+            sublot_remainder_policy: SublotRemainderPolicy::Cancel,
+            min_quantity: 0,
+            level_retention: 0,
+            retained_levels: Vec::new(),
+            hidden_bids: BTreeMap::new(),
+            hidden_asks: BTreeMap::new(),
+            mark_price: None,
+            stop_orders: Vec::new(),
+            trade_history: VecDeque::new(),
+            trade_history_capacity: 0,
+            trade_count: 0,
+            traded_volume: 0,
+            last_trade_price: None,
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Replaces the clock used to timestamp incoming orders
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Replaces the generator of new order ids, random by default
+    pub fn with_id_generator(mut self, id_generator: impl IdGenerator + 'static) -> Self {
+        self.id_generator = Box::new(id_generator);
+        self
+    }
+
+    /// Installs a listener notified of book changes, replacing any previous one
+    pub fn with_listener(mut self, listener: impl BookListener + 'static) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Sends every change to the book to `handler` as it happens: orders
+    /// resting, trades, cancellations and amendments. Replaces any previous
+    /// handler.
+    pub fn set_event_handler(&mut self, handler: Box<dyn FnMut(BookEvent)>) {
+        self.event_handler = Some(EventHandler(handler));
+    }
+
+    /// Starts the book in the given session state
+    pub const fn with_session_state(mut self, session_state: SessionState) -> Self {
+        self.session_state = session_state;
+        self
+    }
+
+    pub const fn session_state(&self) -> SessionState {
+        self.session_state
+    }
+
+    pub const fn set_session_state(&mut self, session_state: SessionState) {
+        self.session_state = session_state;
+    }
+
+    pub const fn with_remainder_rest_policy(mut self, policy: RemainderRestPolicy) -> Self {
+        self.remainder_rest_policy = policy;
+        self
+    }
+
+    /// Caps the quantity of a single order: `try_place_order` refuses
+    /// anything larger and `place_sliced` cuts it into children of at most
+    /// this size. `place_order` takes any quantity as is.
+    pub const fn with_max_order_quantity(mut self, max_order_quantity: i32) -> Self {
+        self.max_order_quantity = Some(max_order_quantity);
+        self
+    }
+
+    /// Caps the number of price levels on each side. Whenever resting an
+    /// order pushes a side over the cap, the worst-priced level on it is
+    /// evicted and its orders cancelled, which can be the new order's own
+    /// level. Empty levels kept by level retention neither count nor get
+    /// evicted. Evicted orders are reported through
+    /// `BookListener::on_orders_evicted`.
+    pub const fn with_max_levels_per_side(mut self, max_levels: usize) -> Self {
+        self.max_levels_per_side = Some(max_levels);
+        self
+    }
+
+    /// Chooses which end of a price level incoming orders fill against.
+    /// Levels always queue orders in arrival order, under `Lifo` matching
+    /// just starts from the newest one and under `ProRata` it shares the
+    /// incoming quantity across the whole level.
+    pub const fn with_level_discipline(mut self, discipline: LevelDiscipline) -> Self {
+        self.level_discipline = discipline;
+        self
+    }
+
+    /// Chooses what happens when an order would trade against a resting
+    /// order of the same owner. Orders without an owner always trade.
+    pub const fn with_self_trade_policy(mut self, policy: SelfTradePolicy) -> Self {
+        self.self_trade_policy = policy;
+        self
+    }
+
+    /// Chooses the price lit trades execute at, the resting order's by
+    /// default. Market orders have no limit to price at, so they always
+    /// trade at the resting price. Hidden orders keep trading at the lit
+    /// midpoint, and quotes and fill previews still walk resting prices.
+    pub const fn with_trade_pricing_policy(mut self, policy: TradePricingPolicy) -> Self {
+        self.trade_pricing_policy = policy;
+        self
+    }
+
+    /// Smallest quantity that may rest on `side`, modelling market-maker
+    /// quoting obligations. Orders of any size can still trade
+    /// aggressively, but whatever would rest below the minimum, a fresh order
+    /// or a remainder, is cancelled instead.
+    pub const fn with_min_quote_size(mut self, side: Side, min_quantity: i32) -> Self {
+        match side {
+            Side::Buy => self.min_bid_quote_size = min_quantity,
+            Side::Sell => self.min_ask_quote_size = min_quantity,
+        }
+        self
+    }
+
+    /// Whether an order priced exactly at the best opposite price trades
+    /// (the default) or rests alongside it, for venues where only strictly
+    /// better prices are marketable. Resting at the same price leaves the
+    /// book locked.
+    pub const fn with_equal_price_crosses(mut self, equal_price_crosses: bool) -> Self {
+        self.equal_price_crosses = equal_price_crosses;
+        self
+    }
+
+    /// Stops matching an incoming order once it has generated `max_trades`
+    /// trades, bounding the work and output of a single placement against
+    /// many small resting orders. The unfilled remainder is then handled like
+    /// any other, so a limit order rests it at its price even if makers are
+    /// still left there, locking the book at that price.
+    pub const fn with_max_trades_per_order(mut self, max_trades: usize) -> Self {
+        self.max_trades_per_order = Some(max_trades);
+        self
+    }
+
+    /// Divides quantities by `scale` in market-data output, for venues that
+    /// store quantities as e.g. shares but publish lots. Stored quantities
+    /// are unaffected. Output quantities get as many decimals as a power of
+    /// ten scale needs, so 250 at a scale of 100 is 2.50; other scales round
+    /// to that many. Panics unless `scale` is positive.
+    pub const fn with_quantity_scale(mut self, scale: i32) -> Self {
+        assert!(scale > 0, "quantity scale must be positive");
+        self.quantity_scale = scale;
+        self
+    }
+
+    /// Treats prices as fixed-point with `decimals` decimal places, so with 2
+    /// decimals `Price(10050)` is 100.50. Matching works on the stored
+    /// integers as always; market-data output shows the decimal price, with
+    /// exactly `decimals` decimals. Prices given as `i32` are taken as whole
+    /// smallest units, which are whole currency units only at the default of
+    /// 0 decimals. Panics above 18 decimals, more than an `i64` price has
+    /// digits for.
+    pub const fn with_price_decimals(mut self, decimals: u32) -> Self {
+        assert!(decimals <= MAX_PRICE_DECIMALS, "at most 18 price decimals are supported");
+        self.price_decimals = decimals;
+        self
+    }
+
+    /// Requires prices going through `try_place_order` to be a multiple of
+    /// `tick`, in the book's price units
+    pub const fn with_tick_size(mut self, tick: i64) -> Self {
+        self.tick_size = tick;
+        self
+    }
+
+    /// Requires quantities going through `try_place_order` to be a multiple
+    /// of `lot`. Orders placed without validation can still rest an odd lot,
+    /// and a whole-lot order trading against one is left with a sub-lot
+    /// remainder, handled by the sub-lot remainder policy.
+    pub const fn with_lot_size(mut self, lot: i32) -> Self {
+        self.lot_size = lot;
+        self
+    }
+
+    /// Chooses whether the odd quantity left after an order trades, below a
+    /// whole lot, is dropped (the default) or rests with the rest of the
+    /// remainder. Orders that rest without trading are left as they are.
+    pub const fn with_sublot_remainder_policy(mut self, policy: SublotRemainderPolicy) -> Self {
+        self.sublot_remainder_policy = policy;
+        self
+    }
+
+    /// Requires quantities going through `try_place_order` to be at least
+    /// `min`
+    pub const fn with_min_quantity(mut self, min: i32) -> Self {
+        self.min_quantity = min;
+        self
+    }
+
+    /// Keeps emptied price levels in the book at quantity 0 for `operations`
+    /// more operations before removing them, so levels don't flicker in and
+    /// out of the published market data. Matching and best prices skip them.
+    pub const fn with_level_retention(mut self, operations: u64) -> Self {
+        self.level_retention = operations;
+        self
+    }
+
+    /// Starts a new operation on the book, returning its sequence number.
+    /// Retained empty levels whose grace period is over are removed here.
+    fn begin_operation(&mut self) -> u64 {
+        self.sequence += 1;
+
+        if !self.retained_levels.is_empty() {
+            let sequence = self.sequence;
+            let (expired, retained) = std::mem::take(&mut self.retained_levels)
+                .into_iter()
+                .partition(|&(_, _, retained_until)| retained_until < sequence);
+            self.retained_levels = retained;
+            for (side, price, _) in expired {
+                self.drop_level_if_empty(side, price);
+            }
+        }
+
+        self.sequence
+    }
+
+    /// Price as published in market data
+    fn display_price(&self, price: Price) -> String {
+        fixed_point(i128::from(*price), 10i128.pow(self.price_decimals), self.price_decimals)
+    }
+
+    /// Quantity as published in market data
+    fn display_quantity(&self, quantity: Quantity) -> String {
+        let decimals = (self.quantity_scale - 1).checked_ilog10().map_or(0, |digits| digits + 1);
+        fixed_point(i128::from(*quantity), i128::from(self.quantity_scale), decimals)
+    }
+
+    /// Keeps the last `capacity` trades for history-based analytics. No
+    /// history is kept by default.
+    pub fn with_trade_history(mut self, capacity: usize) -> Self {
+        self.trade_history = VecDeque::with_capacity(capacity);
+        self.trade_history_capacity = capacity;
+        self
+    }
+
+    pub const fn mark_price(&self) -> Option<Price> {
+        self.mark_price
+    }
+
+    /// Sets the reference price resting orders are marked against
+    pub const fn set_mark_price(&mut self, price: Price) {
+        self.mark_price = Some(price);
+    }
+
+    /// Number of operations applied to the book so far
+    pub const fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Book sized for about `levels` price levels: level queues are
+    /// pre-allocated for them, see `reserve`, and the order index for as
+    /// many orders. The price maps themselves can't be pre-sized.
+    pub fn with_capacity(levels: usize) -> Self {
+        let mut book = Self::new();
+        book.reserve(levels);
+        book.order_index.reserve(levels);
+        book
+    }
+
+    /// Pre-allocates queues for roughly `levels_hint` price levels so that
+    /// known workloads don't allocate while building up the book. These
+    /// are kept even beyond the limit on recycled queues, see
+    /// `SPARE_LEVEL_LIMIT`.
+    pub fn reserve(&mut self, levels_hint: usize) {
+        let missing = levels_hint.saturating_sub(self.spare_levels.len());
+        self.spare_levels.reserve(missing);
+        self.spare_levels
+            .extend((0..missing).map(|_| VecDeque::with_capacity(RESERVED_LEVEL_CAPACITY)));
+    }
+
+    /// Releases memory held beyond what the book currently needs: trims
+    /// every level queue to its length, drops retained empty levels and
+    /// frees the pool of spare level queues. Meant for long-running books
+    /// after heavy trading.
+    pub fn shrink_to_fit(&mut self) {
+        for book in [&mut self.bids, &mut self.asks, &mut self.hidden_bids, &mut self.hidden_asks] {
+            book.retain(|_, orders| !orders.is_empty());
+            book.values_mut().for_each(VecDeque::shrink_to_fit);
+        }
+        self.order_index.shrink_to_fit();
+        self.retained_levels = Vec::new();
+        self.spare_levels = Vec::new();
+    }
+
+    /// Empties the book as if it had just been built: every order, lit,
+    /// hidden or stop, the trade history, counters and positions, the last
+    /// trade and mark prices and the sequence number. Settings, the clock,
+    /// the id generator, the listener and event handler and the session
+    /// state are kept. The event handler gets a single `BookEvent::Cleared`
+    /// rather than an event per removed order, and the listener isn't
+    /// notified. Level queues go back to the spare pool as far as it has
+    /// room.
+    pub fn clear(&mut self) {
+        for book in [&mut self.bids, &mut self.asks, &mut self.hidden_bids, &mut self.hidden_asks] {
+            for (_, orders) in std::mem::take(book) {
+                recycle_level(&mut self.spare_levels, orders);
+            }
+        }
+        self.order_index.clear();
+        self.retained_levels.clear();
+        self.stop_orders.clear();
+        self.trade_history.clear();
+        self.trade_count = 0;
+        self.traded_volume = 0;
+        self.last_trade_price = None;
+        self.positions.clear();
+        self.mark_price = None;
+        self.sequence = 0;
+        emit(&mut self.event_handler, || BookEvent::Cleared);
+    }
+
+    /// Matches a new limit order and rests its remainder, returning the id
+    /// assigned to it, its trades and how much of it is left in the book.
+    /// Trades of any stop orders it triggers follow its own.
+    /// The price and quantity are taken as is, see `try_place_order` to
+    /// validate them first.
+    pub fn place_order(&mut self, side: Side, price: i64, quantity: i32) -> PlaceResult {
+        self.place_order_as(0, side, price, quantity)
+    }
+
+    /// Places an order like `place_order`, refusing a non-positive quantity
+    /// or price, a price off the tick grid, a quantity that isn't a whole
+    /// number of lots of at least the minimum quantity, or one above the
+    /// maximum order quantity, before anything is matched
+    pub fn try_place_order(&mut self, side: Side, price: i64, quantity: i32) -> Result<PlaceResult, OrderError> {
+        self.validate_order(price, quantity)?;
+        Ok(self.place_order(side, price, quantity))
+    }
+
+    /// Checks an order's price and quantity against the book's rules, see
+    /// `try_place_order`
+    fn validate_order(&self, price: i64, quantity: i32) -> Result<(), OrderError> {
+        if quantity <= 0 {
+            return Err(OrderError::NonPositiveQuantity);
+        }
+        if price <= 0 {
+            return Err(OrderError::NonPositivePrice);
+        }
+        if self.tick_size > 1 && price % self.tick_size != 0 {
+            return Err(OrderError::InvalidTick);
+        }
+        if (self.lot_size > 1 && quantity % self.lot_size != 0) || quantity < self.min_quantity {
+            return Err(OrderError::InvalidLotSize);
+        }
+        if self.max_order_quantity.is_some_and(|max| quantity > max) {
+            return Err(OrderError::QuantityAboveMax);
+        }
+        Ok(())
+    }
+
+    /// Places `orders` one after the other, each seeing the book as the
+    /// earlier ones left it, and returns their results in order
+    pub fn place_batch(&mut self, orders: &[(Side, i64, i32)]) -> Vec<PlaceResult> {
+        orders
+            .iter()
+            .map(|&(side, price, quantity)| self.place_order(side, price, quantity))
+            .collect()
+    }
+
+    /// Like `place_batch`, but validates every order up front the way
+    /// `try_place_order` does and places nothing unless all of them pass.
+    /// On failure returns the index of the first invalid order and why.
+    pub fn place_batch_atomic(&mut self, orders: &[(Side, i64, i32)]) -> Result<Vec<PlaceResult>, (usize, OrderError)> {
+        for (index, &(_, price, quantity)) in orders.iter().enumerate() {
+            self.validate_order(price, quantity).map_err(|error| (index, error))?;
+        }
+        Ok(self.place_batch(orders))
+    }
+
+    /// Places an order like `place_order` on behalf of `owner`, subject to
+    /// the self-trade policy
+    pub fn place_order_as(&mut self, owner: u64, side: Side, price: i64, quantity: i32) -> PlaceResult {
+        let mut trades = Vec::new();
+        let order = self.new_order(side, price.into(), quantity.into()).with_owner(owner);
+        let (order_id, remaining, discarded) = self.execute_order(order, true, &mut trades);
+        PlaceResult { order_id, trades, remaining, discarded }
+    }
+
+    /// Places an order like `place_order_as`, after the checks of
+    /// `try_place_order`. When the self-trade policy cancels incoming orders,
+    /// an order that would reach one of `owner`'s resting orders is refused
+    /// up front instead of trading partway and being cancelled.
+    pub fn try_place_order_as(&mut self, owner: u64, side: Side, price: i64, quantity: i32) -> Result<PlaceResult, OrderError> {
+        self.validate_order(price, quantity)?;
+        if self.self_trade_policy == SelfTradePolicy::CancelIncoming && self.reaches_own_order(owner, side, Price(price), quantity) {
+            return Err(OrderError::SelfTradePrevented);
+        }
+        Ok(self.place_order_as(owner, side, price, quantity))
+    }
+
+    /// Places a reduce-only order for `owner`, clamped at placement so that
+    /// it can only bring the owner's position towards flat: to the position
+    /// less what the owner already has resting on `side`, so it can't flip
+    /// the position whatever order those fill in. A sell reduces a long
+    /// position and a buy a short one. Refused with `NoPositionToReduce` if
+    /// nothing is left to submit.
+    pub fn place_reduce_only(&mut self, owner: u64, side: Side, price: i64, quantity: i32) -> Result<PlaceResult, OrderError> {
+        if quantity <= 0 {
+            return Err(OrderError::NonPositiveQuantity);
+        }
+        let position = match side {
+            Side::Buy => -self.position(owner),
+            Side::Sell => self.position(owner),
+        };
+        let reducible = position - self.resting_quantity_of(owner, side);
+        let submitted = i64::from(quantity).min(reducible);
+        if submitted <= 0 {
+            return Err(OrderError::NoPositionToReduce);
+        }
+        Ok(self.place_order_as(owner, side, price, submitted as i32))
+    }
+
+    /// Net quantity `owner` has bought minus sold in this book, 0 for an
+    /// owner that hasn't traded. Orders without an owner aren't tracked.
+    pub fn position(&self, owner: u64) -> i64 {
+        self.positions.get(&owner).copied().unwrap_or(0)
+    }
+
+    /// Quantity `owner` has resting on `side`, lit or hidden, iceberg
+    /// reserves included
+    fn resting_quantity_of(&self, owner: u64, side: Side) -> i64 {
+        let hidden_book = match side {
+            Side::Buy => &self.hidden_bids,
+            Side::Sell => &self.hidden_asks,
+        };
+        self.level_queues(side)
+            .flat_map(|(_, orders)| orders)
+            .chain(hidden_book.values().flatten())
+            .filter(|order| order.owner == owner)
+            .map(|order| i64::from(*order.quantity) + i64::from(*order.reserve))
+            .sum()
+    }
+
+    /// Whether an order of `owner` would come to a resting order of the same
+    /// owner among the lit orders it fills against
+    fn reaches_own_order(&self, owner: u64, side: Side, limit: Price, quantity: i32) -> bool {
+        if owner == 0 {
+            return false;
+        }
+        let book_side = side.opposite();
+        let mut remaining = i64::from(quantity);
+        for (price, orders) in self.level_queues(book_side) {
+            if remaining <= 0 || !is_marketable(side, limit, price, self.equal_price_crosses) {
+                break;
+            }
+            // A pro-rata fill smaller than the level touches every order in it
+            if self.level_discipline == LevelDiscipline::ProRata && remaining < level_volume(orders) {
+                return orders.iter().any(|order| order.owner == owner);
+            }
+            for order in in_fill_order(orders, self.level_discipline) {
+                if order.owner == owner {
+                    return true;
+                }
+                remaining -= i64::from(*order.quantity);
+                if remaining <= 0 {
+                    break;
+                }
+            }
+        }
+        false
+    }
+
+    /// Places an order like `place_order` under a caller-supplied `id`,
+    /// refusing it if an order with that id is still resting or waiting as
+    /// a stop order, which keeps its id once it triggers. Re-sending an
+    /// order that already rests therefore can't book it twice.
+    pub fn place_order_with_id(&mut self, id: Uuid, side: Side, price: i64, quantity: i32) -> Result<PlaceResult, OrderError> {
+        if self.order_index.contains_key(&id) || self.stop_orders.iter().any(|stop| stop.id == id) {
+            return Err(OrderError::DuplicateId);
+        }
+        let mut trades = Vec::new();
+        let order = self.new_order_with_id(id, side, price.into(), quantity.into());
+        let (order_id, remaining, discarded) = self.execute_order(order, true, &mut trades);
+        Ok(PlaceResult { order_id, trades, remaining, discarded })
+    }
+
+    /// Places a good-till-date order like `place_order`. Its remainder rests
+    /// until `purge_expired` runs at or after `expires_at`.
+    pub fn place_gtd(&mut self, side: Side, price: i64, quantity: i32, expires_at: u64) -> PlaceResult {
+        let mut trades = Vec::new();
+        let order = self.new_order(side, price.into(), quantity.into()).with_expiry(expires_at);
+        let (order_id, remaining, discarded) = self.execute_order(order, true, &mut trades);
+        PlaceResult { order_id, trades, remaining, discarded }
+    }
+
+    /// Places an order like `place_order`, and also reports how much of it
+    /// filled and where its remainder landed in the queue
+    pub fn place_order_ack(&mut self, side: Side, price: i64, quantity: i32) -> (Ack, Vec<Trade>) {
+        let PlaceResult { order_id: id, trades, .. } = self.place_order(side, price, quantity);
+        let location = self.locate_order(id);
+        let filled: i32 = trades.iter().map(|trade| *trade.quantity).sum();
+
+        let ack = Ack {
+            id,
+            resting_price: location.map(|(_, price, _)| price),
+            queue_position: location.map(|(_, _, position)| position),
+            filled: filled.into(),
+        };
+        (ack, trades)
+    }
+
+    /// Places an order like `place_order`, then checks every trade it
+    /// produced against the matching invariants: positive quantity, a price
+    /// within the order's limit, and distinct maker and taker. An anomaly
+    /// means a bug in the engine or a corrupted book rather than a rejected
+    /// order, and the book has already been updated when it is reported.
+    pub fn place_order_checked(&mut self, side: Side, price: i64, quantity: i32) -> Result<Vec<Trade>, MatchAnomaly> {
+        let trades = self.place_order(side, price, quantity).trades;
+
+        for (index, trade) in trades.iter().enumerate() {
+            if *trade.quantity <= 0 {
+                return Err(MatchAnomaly::ZeroQuantity { trade: index });
+            }
+            let within_limit = match side {
+                Side::Buy => *trade.price <= price,
+                Side::Sell => *trade.price >= price,
+            };
+            if !within_limit {
+                return Err(MatchAnomaly::PriceOutsideLimit { trade: index, price: trade.price });
+            }
+            if trade.maker_id == trade.taker_id {
+                return Err(MatchAnomaly::SelfMatch { trade: index });
+            }
+        }
+
+        Ok(trades)
+    }
+
+    /// Places an order in child slices of at most `max_order_quantity` each,
+    /// one after the other, instead of as a single oversized order. Returns
+    /// the child order ids and the trades of all children combined. Without
+    /// a configured maximum the order goes in as a single child.
+    pub fn place_sliced(&mut self, side: Side, price: i64, quantity: i32) -> (Vec<Uuid>, Vec<Trade>) {
+        let slice_size = self.max_order_quantity.unwrap_or(quantity).max(1);
+        let mut child_ids = Vec::new();
+        let mut trades = Vec::new();
+
+        let mut remaining = quantity;
+        while remaining > 0 {
+            let child_quantity = remaining.min(slice_size);
+            let child = self.place_order(side, price, child_quantity);
+            child_ids.push(child.order_id);
+            trades.extend(child.trades);
+            remaining -= child_quantity;
+        }
+
+        (child_ids, trades)
+    }
+
+    /// Does the work of `place_order`, writing the trades into the caller's
+    /// buffer, which must be empty. Returns the order's id, the quantity
+    /// that rested and the quantity the book refused to rest.
+    fn execute_limit_order(&mut self, side: Side, price: i64, quantity: i32, trades: &mut Vec<Trade>) -> (Uuid, Quantity, Quantity) {
+        let order = self.new_order(side, price.into(), quantity.into());
+        self.execute_order(order, true, trades)
+    }
+
+    /// Starts a new operation and builds a fresh order entering the book in
+    /// it, stamped with the current time and the operation's sequence number
+    fn new_order(&mut self, side: Side, price: Price, quantity: Quantity) -> Order {
+        let id = self.id_generator.next_id();
+        self.new_order_with_id(id, side, price, quantity)
+    }
+
+    /// `new_order` under an id the caller already has, leaving the id
+    /// generator alone
+    fn new_order_with_id(&mut self, id: Uuid, side: Side, price: Price, quantity: Quantity) -> Order {
+        let sequence = self.begin_operation();
+        Order::new(id, side, price, quantity)
+            .with_timestamp(self.clock.now())
+            .with_sequence(sequence)
+    }
+
+    /// Matches an incoming order against the book and, if `rests`, queues
+    /// its remainder; otherwise the remainder is dropped
+    fn execute_order(&mut self, incoming_order: Order, rests: bool, trades: &mut Vec<Trade>) -> (Uuid, Quantity, Quantity) {
+        self.execute_order_to_depth(incoming_order, rests, usize::MAX, trades)
+    }
+
+    /// `execute_order` that matches against at most `max_levels` lit price
+    /// levels
+    fn execute_order_to_depth(&mut self, mut incoming_order: Order, rests: bool, max_levels: usize, trades: &mut Vec<Trade>) -> (Uuid, Quantity, Quantity) {
+        debug_assert!(trades.is_empty(), "trade buffer must start empty");
+
+        let (id, side) = (incoming_order.id, incoming_order.side);
+
+        // Before the open everything rests, crossed or not, until the auction
+        if self.session_state == SessionState::PreOpen {
+            let (remaining, discarded) = if rests { self.rest_remainder(incoming_order, trades) } else { (Quantity(0), Quantity(0)) };
+            return (id, remaining, discarded);
         }
+
+        // Trades are recorded against the book as the order found it
+        let touch = self.touch();
+
+        // Hidden liquidity gets the first look
+        self.match_hidden(&mut incoming_order, trades);
+
+        // A buy matches against the asks, a sell against the bids. Hidden
+        // fills are already in `trades` and count towards the trade limit.
+        let max_trades = self.match_rules().max_trades;
+        self.match_order(&mut incoming_order, side.opposite(), max_trades, max_levels, trades);
+        let (mut remaining, mut discarded) = (Quantity(0), Quantity(0));
+        if rests && *incoming_order.quantity > 0 {
+            (remaining, discarded) = self.rest_remainder(incoming_order, trades);
+        }
+
+        self.record_trades(trades, touch);
+        self.trigger_stops(trades);
+        (id, remaining, discarded)
+    }
+
+    /// Holds a stop order until a trade at or through `trigger`, then enters
+    /// it as a limit order at `limit`, or as a market order if there is no
+    /// limit. Returns the id the order will trade and rest under, or refuses
+    /// a non-positive quantity.
+    pub fn place_stop(&mut self, side: Side, trigger: i64, limit: Option<i64>, quantity: i32) -> Result<Uuid, OrderError> {
+        if quantity <= 0 {
+            return Err(OrderError::NonPositiveQuantity);
+        }
+        self.begin_operation();
+        let id = self.id_generator.next_id();
+        self.stop_orders.push(StopOrder {
+            id,
+            side,
+            trigger: trigger.into(),
+            limit: limit.map(Price::from),
+            quantity: quantity.into(),
+        });
+        Ok(id)
+    }
+
+    /// Cancels the pending stop order with `id` before it triggers and
+    /// returns it. A triggered stop rests as an ordinary order, see
+    /// `cancel_order`.
+    pub fn cancel_stop(&mut self, id: Uuid) -> Result<StopOrder, OrderError> {
+        let position = self.stop_orders.iter().position(|stop| stop.id == id).ok_or(OrderError::OrderNotFound)?;
+        self.begin_operation();
+        Ok(self.stop_orders.remove(position))
+    }
+
+    /// Enters every stop order triggered by the last of `trades`, in arrival
+    /// order, and appends their trades to `trades`. Stops therefore always
+    /// execute after the fills that triggered them, and a triggered stop's
+    /// own trades can in turn trigger further stops.
+    fn trigger_stops(&mut self, trades: &mut Vec<Trade>) {
+        let Some(last_price) = trades.last().map(|trade| trade.price) else {
+            return;
+        };
+        if self.stop_orders.is_empty() {
+            return;
+        }
+
+        let (triggered, waiting) = std::mem::take(&mut self.stop_orders)
+            .into_iter()
+            .partition::<Vec<_>, _>(|stop| stop.is_triggered_by(last_price));
+        self.stop_orders = waiting;
+
+        for stop in triggered {
+            let (price, rests) = match stop.limit {
+                Some(limit) => (limit, true),
+                None => (market_limit(stop.side), false),
+            };
+            let order = self.new_order_with_id(stop.id, stop.side, price, stop.quantity);
+
+            let mut stop_trades = Vec::new();
+            self.execute_order(order, rests, &mut stop_trades);
+            trades.append(&mut stop_trades);
+        }
+    }
+
+    /// Buys or sells `quantity` at whatever prices the opposite side offers,
+    /// walking it level by level until the quantity is filled or the side
+    /// runs dry. A market order never rests: against an empty book it simply
+    /// produces no trades, and any unfilled quantity is dropped. Before the
+    /// open there is nothing to trade against, so it is dropped entirely.
+    pub fn place_market_order(&mut self, side: Side, quantity: i32) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let order = self.new_order(side, market_limit(side), quantity.into());
+        self.execute_order(order, false, &mut trades);
+        trades
+    }
+
+    /// Market order that takes liquidity from at most `max_levels` lit price
+    /// levels, as a guard against slippage. Whatever those levels and any
+    /// hidden orders can't fill is dropped like any other unfilled market
+    /// quantity.
+    pub fn place_market_order_with_max_levels(&mut self, side: Side, quantity: i32, max_levels: usize) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let order = self.new_order(side, market_limit(side), quantity.into());
+        self.execute_order_to_depth(order, false, max_levels, &mut trades);
+        trades
+    }
+
+    /// Places an iceberg order for `total_qty` that shows at most
+    /// `display_qty` at a time. It matches in full on entry; once resting,
+    /// only the visible slice counts towards best prices, depth and volume.
+    /// Each time a slice fills, the next one is released from the reserve
+    /// at the end of the level's queue that fills last: the back, or the
+    /// front under LIFO.
+    pub fn place_iceberg(&mut self, side: Side, price: i64, total_qty: i32, display_qty: i32) -> PlaceResult {
+        let mut trades = Vec::new();
+        let order = self.new_order(side, price.into(), total_qty.into()).with_display_size(display_qty.into());
+        let (order_id, remaining, discarded) = self.execute_order(order, true, &mut trades);
+        PlaceResult { order_id, trades, remaining, discarded }
+    }
+
+    /// Immediate-or-cancel: matches like a limit order, but whatever cannot
+    /// fill right away is discarded instead of resting
+    pub fn place_ioc(&mut self, side: Side, price: i64, quantity: i32) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let order = self.new_order(side, price.into(), quantity.into());
+        self.execute_order(order, false, &mut trades);
+        trades
+    }
+
+    /// Fill-or-kill: executes the whole quantity immediately or nothing at
+    /// all. Returns `None`, with the book untouched, when the order could
+    /// only partially fill.
+    pub fn place_fok(&mut self, side: Side, price: i64, quantity: i32) -> Option<Vec<Trade>> {
+        let (_, fillable) = self.preview_fill(side, price, quantity);
+        if quantity <= 0 || fillable < i64::from(quantity) {
+            return None;
+        }
+        Some(self.place_ioc(side, price, quantity))
+    }
+
+    /// Rests an order only if it would not trade on entry, rejecting it when
+    /// it would cross the best opposite price or has no quantity to rest. A
+    /// post-only order is never a taker, hidden liquidity included.
+    pub fn place_post_only(&mut self, side: Side, price: i64, quantity: i32) -> Result<Uuid, PostOnlyRejected> {
+        if quantity <= 0 {
+            return Err(PostOnlyRejected::NonPositiveQuantity);
+        }
+        if let Some((best_opposite, _)) = self.levels(side.opposite()).next()
+            && is_marketable(side, Price(price), best_opposite, self.equal_price_crosses)
+        {
+            return Err(PostOnlyRejected::WouldCross { best_opposite });
+        }
+
+        let order = self.new_order(side, price.into(), quantity.into());
+        let id = order.id;
+        self.rest_remainder(order, &[]);
+        Ok(id)
+    }
+
+    /// Places every order in sequence and returns the total number of trades
+    /// they generated. Trades themselves are discarded, with a single buffer
+    /// reused across orders, for bulk simulation where only the final book
+    /// and the trade count matter.
+    pub fn apply_all(&mut self, orders: &[(Side, i64, i32)]) -> usize {
+        let mut trades = Vec::new();
+        let mut trade_count = 0;
+        for &(side, price, quantity) in orders {
+            trades.clear();
+            self.execute_limit_order(side, price, quantity, &mut trades);
+            trade_count += trades.len();
+        }
+        trade_count
+    }
+
+    /// Builds a fresh book, places every order in sequence and returns all
+    /// trades in execution order, for deterministic scenario evaluation
+    pub fn run_scenario(orders: &[(Side, i64, i32)]) -> Vec<Trade> {
+        let mut book = OrderBook::new();
+        orders
+            .iter()
+            .flat_map(|&(side, price, quantity)| book.place_order(side, price, quantity).trades)
+            .collect()
+    }
+
+    /// Net signed quantity each order id traded across `trades`, positive for
+    /// buying. Lets downstream systems reconcile fills against the tape on
+    /// their own.
+    pub fn reconstruct_positions(trades: &[Trade]) -> HashMap<Uuid, i64> {
+        let mut positions = HashMap::new();
+        for trade in trades {
+            let quantity = i64::from(*trade.quantity);
+            let taker_quantity = match trade.taker_side {
+                Side::Buy => quantity,
+                Side::Sell => -quantity,
+            };
+            *positions.entry(trade.taker_id).or_insert(0) += taker_quantity;
+            *positions.entry(trade.maker_id).or_insert(0) -= taker_quantity;
+        }
+        positions
+    }
+
+    /// Builds a book from aggregated L2 levels, each level becoming a single
+    /// resting order with a fresh id. Levels with no quantity are skipped.
+    /// The data is taken as is, see `from_levels_strict` to validate it.
+    pub fn from_levels(bids: &[(Price, Quantity)], asks: &[(Price, Quantity)]) -> OrderBook {
+        let mut book = OrderBook::new();
+        for (side, levels) in [(Side::Buy, bids), (Side::Sell, asks)] {
+            for &(price, quantity) in levels.iter().filter(|(_, quantity)| **quantity > 0) {
+                let order = book.new_order(side, price, quantity);
+                book.add_order_to_book(order);
+            }
+        }
+        book
+    }
+
+    /// Copies every resting order, lit and hidden, in priority order, and
+    /// every pending stop order
+    pub fn snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            bids: queued_orders(self.bids.values().rev()),
+            asks: queued_orders(self.asks.values()),
+            hidden_bids: queued_orders(self.hidden_bids.values().rev()),
+            hidden_asks: queued_orders(self.hidden_asks.values()),
+            stops: self.stop_orders.clone(),
+            sequence: self.sequence,
+        }
+    }
+
+    /// Rebuilds a book from a snapshot, with every order keeping its id and
+    /// its place in the queue, and the stop orders their arrival order.
+    /// Settings aren't part of a snapshot, the book starts out with the
+    /// defaults.
+    pub fn from_snapshot(snapshot: BookSnapshot) -> OrderBook {
+        let mut book = OrderBook::new();
+        for order in snapshot.bids.into_iter().chain(snapshot.asks) {
+            book.add_order_to_book(order);
+        }
+        for order in snapshot.hidden_bids.into_iter().chain(snapshot.hidden_asks) {
+            book.add_hidden_order_to_book(order);
+        }
+        book.stop_orders = snapshot.stops;
+        book.sequence = snapshot.sequence;
+        book
+    }
+
+    /// Rebuilds a book by applying, in order, the events another book sent
+    /// to its event handler, lit and hidden orders alike, with the owners'
+    /// positions rebuilt from the trades. An amendment that increases an
+    /// order's quantity re-queues it with the replaying book's own clock and
+    /// sequence, which the original stamps aren't recorded to match.
+    pub fn replay(events: impl IntoIterator<Item = BookEvent>) -> OrderBook {
+        let mut book = OrderBook::new();
+        for event in events {
+            book.apply_event(event);
+        }
+        book
+    }
+
+    /// Applies one recorded event, see `replay`
+    fn apply_event(&mut self, event: BookEvent) {
+        match event {
+            BookEvent::OrderAdded(order) => self.insert_replayed(order, false),
+            BookEvent::HiddenOrderAdded(order) => self.insert_replayed(order, true),
+            BookEvent::OrderCancelled(id) => {
+                let _ = self.cancel_order(id);
+            }
+            BookEvent::OrderAmended { id, new_qty, .. } => {
+                let _ = self.amend_quantity(id, *new_qty);
+            }
+            BookEvent::TradeExecuted(trade) => {
+                let touch = self.touch();
+                // An auction's taker is a resting bid, otherwise the taker
+                // only rests after its trades and isn't found
+                for id in [trade.maker_id, trade.taker_id] {
+                    self.fill_resting(id, trade.quantity, trade.timestamp);
+                }
+                let (buyer, seller) = match trade.taker_side {
+                    Side::Buy => (trade.taker_owner, trade.maker_owner),
+                    Side::Sell => (trade.maker_owner, trade.taker_owner),
+                };
+                record_position(&mut self.positions, buyer, seller, *trade.quantity);
+                self.record_trades(&[trade], touch);
+            }
+            BookEvent::Cleared => self.clear(),
+        }
+    }
+
+    /// Queues a replayed order in the lit or the hidden book. By sequence,
+    /// so a split's second half lands right behind the original rather than
+    /// at the back.
+    fn insert_replayed(&mut self, order: Order, hidden: bool) {
+        let book = match (order.side, hidden) {
+            (Side::Buy, false) => &mut self.bids,
+            (Side::Sell, false) => &mut self.asks,
+            (Side::Buy, true) => &mut self.hidden_bids,
+            (Side::Sell, true) => &mut self.hidden_asks,
+        };
+        let orders = book.entry(order.price).or_default();
+        let position = orders.partition_point(|resting| resting.sequence <= order.sequence);
+        self.order_index.insert(order.id, order.price);
+        orders.insert(position, order);
+    }
+
+    /// Takes a replayed trade's quantity off the resting order with `id`, lit
+    /// or hidden, if there is one, dropping the order once filled or showing
+    /// its next iceberg slice like matching does
+    fn fill_resting(&mut self, id: Uuid, quantity: Quantity, timestamp: u64) {
+        let Some((orders, position)) = self.find_resting_order_mut(id) else {
+            return;
+        };
+        let resting = &mut orders[position];
+        resting.quantity = Quantity(*resting.quantity - *quantity);
+        if *resting.quantity > 0 {
+            return;
+        }
+
+        let (side, price) = (resting.side, resting.price);
+        if let Some(mut filled) = orders.remove(position) {
+            if filled.replenish() {
+                // Behind the rest of the level, as the original did with the
+                // taker's sequence
+                let sequence = orders.back().map_or(filled.sequence, |last| last.sequence.max(filled.sequence));
+                orders.push_back(filled.with_timestamp(timestamp).with_sequence(sequence));
+            } else {
+                self.order_index.remove(&filled.id);
+            }
+        }
+        self.drop_level_if_empty(side, price);
+        let hidden_book = match side {
+            Side::Buy => &mut self.hidden_bids,
+            Side::Sell => &mut self.hidden_asks,
+        };
+        if hidden_book.get(&price).is_some_and(VecDeque::is_empty) {
+            hidden_book.remove(&price);
+        }
+    }
+
+    /// Like `from_levels`, but refuses snapshots whose bids aren't strictly
+    /// descending, whose asks aren't strictly ascending, or whose best bid
+    /// isn't below the best ask, as a corrupt feed would produce
+    pub fn from_levels_strict(bids: &[(Price, Quantity)], asks: &[(Price, Quantity)]) -> Result<OrderBook, ImportError> {
+        let bids_sorted = bids.windows(2).all(|pair| pair[0].0 > pair[1].0);
+        let asks_sorted = asks.windows(2).all(|pair| pair[0].0 < pair[1].0);
+        if !bids_sorted || !asks_sorted {
+            return Err(ImportError::Unsorted);
+        }
+        if let (Some((best_bid, _)), Some((best_ask, _))) = (bids.first(), asks.first())
+            && best_bid >= best_ask
+        {
+            return Err(ImportError::Crossed);
+        }
+        Ok(Self::from_levels(bids, asks))
+    }
+
+    /// Applies a single logged command, returning any trades it produced
+    pub fn apply(&mut self, command: &Command) -> Vec<Trade> {
+        match *command {
+            Command::Place { side, price, quantity } => self.place_order(side, price, quantity).trades,
+            Command::CancelFront { side, price } => {
+                self.cancel_front(side, price);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Commands that move the best bid and ask to the target levels, for a
+    /// quoting account that owns every resting order. Levels better than a
+    /// target are cancelled outright; at the target price, orders are
+    /// cancelled from the front while the level holds more than the target
+    /// and the shortfall is then topped up. All cancels come before any
+    /// places.
+    pub fn quote_adjustments(&self, target_bid: (Price, Quantity), target_ask: (Price, Quantity)) -> Vec<Command> {
+        let mut cancels = Vec::new();
+        let mut places = Vec::new();
+
+        for (side, (target_price, target_quantity)) in [(Side::Buy, target_bid), (Side::Sell, target_ask)] {
+            let mut resting = 0;
+            for (price, orders) in self.level_queues(side) {
+                let ahead_of_target = match side {
+                    Side::Buy => price > target_price,
+                    Side::Sell => price < target_price,
+                };
+                if !ahead_of_target && price != target_price {
+                    break;
+                }
+
+                let mut level_quantity: i64 = orders.iter().map(|order| i64::from(*order.quantity)).sum();
+                for order in orders {
+                    if !ahead_of_target && level_quantity <= i64::from(*target_quantity) {
+                        break;
+                    }
+                    cancels.push(Command::CancelFront { side, price: *price });
+                    level_quantity -= i64::from(*order.quantity);
+                }
+                if !ahead_of_target {
+                    resting = level_quantity;
+                }
+            }
+
+            let shortfall = i64::from(*target_quantity) - resting;
+            if shortfall > 0 {
+                places.push(Command::Place { side, price: *target_price, quantity: shortfall as i32 });
+            }
+        }
+
+        cancels.extend(places);
+        cancels
+    }
+
+    /// Replays `commands` into a fresh book, checking each trade against
+    /// `expected` as it is produced. Order ids are minted anew on every run,
+    /// so trades are compared on price and quantity. On a mismatch, or if
+    /// the replay produces fewer or more trades than expected, returns the
+    /// index of the first trade that diverged.
+    pub fn replay_verified(commands: &[Command], expected: &[Trade]) -> Result<OrderBook, usize> {
+        let mut book = OrderBook::new();
+        let mut verified = 0;
+
+        for command in commands {
+            for trade in book.apply(command) {
+                match expected.get(verified) {
+                    Some(expected_trade)
+                        if expected_trade.price == trade.price && expected_trade.quantity == trade.quantity =>
+                    {
+                        verified += 1;
+                    }
+                    _ => return Err(verified),
+                }
+            }
+        }
+
+        if verified < expected.len() {
+            return Err(verified);
+        }
+        Ok(book)
+    }
+
+    /// Adds executed trades to the lifetime counters, and to the history
+    /// along with the best bid and ask they were made against
+    fn record_trades(&mut self, trades: &[Trade], touch: Option<(Price, Price)>) {
+        self.trade_count += trades.len() as u64;
+        self.traded_volume += trades.iter().map(|trade| u64::from(trade.quantity.unsigned_abs())).sum::<u64>();
+        if let Some(last_trade) = trades.last() {
+            self.last_trade_price = Some(last_trade.price);
+        }
+
+        if self.trade_history_capacity > 0 {
+            for trade in trades {
+                if self.trade_history.len() == self.trade_history_capacity {
+                    self.trade_history.pop_front();
+                }
+                self.trade_history.push_back(HistoryEntry { sequence: self.sequence, trade: trade.clone(), touch });
+            }
+        }
+    }
+
+    /// Queues what is left of an incoming order after matching, at the price
+    /// the remainder rest policy picks, holding back an iceberg's reserve.
+    /// A traded order's odd lot is dropped unless the sub-lot policy rests
+    /// it, and remainders below the side's minimum quote size are dropped
+    /// altogether. Returns the quantity left resting and the quantity
+    /// dropped.
+    fn rest_remainder(&mut self, mut order: Order, trades: &[Trade]) -> (Quantity, Quantity) {
+        let unfilled = *order.quantity;
+        if self.lot_size > 1 && !trades.is_empty() && self.sublot_remainder_policy == SublotRemainderPolicy::Cancel {
+            order.quantity = Quantity(*order.quantity - *order.quantity % self.lot_size);
+        }
+
+        let min_quote_size = match order.side {
+            Side::Buy => self.min_bid_quote_size,
+            Side::Sell => self.min_ask_quote_size,
+        };
+        if *order.quantity < min_quote_size {
+            return (Quantity(0), Quantity(unfilled));
+        }
+
+        if self.remainder_rest_policy == RemainderRestPolicy::AtLastFillPrice
+            && let Some(last_trade) = trades.last()
+        {
+            order.price = last_trade.price;
+        }
+        let quantity = order.quantity;
+        order.hide_reserve();
+        let rested = if self.add_order_to_book(order) { quantity } else { Quantity(0) };
+        (rested, Quantity(unfilled - *rested))
+    }
+
+    /// Matches `incoming_order` against the lit orders on `book_side`
+    /// across at most `max_levels` price levels, appending its trades to
+    /// `trades` until that holds `max_trades`. Writing into the caller's
+    /// buffer lets bulk callers like `apply_all` reuse one allocation.
+    fn match_order(&mut self, incoming_order: &mut Order, book_side: Side, max_trades: usize, max_levels: usize, trades: &mut Vec<Trade>) {
+        let rules = MatchRules { max_trades, ..self.match_rules() };
+        let equal_price_crosses = self.equal_price_crosses;
+        let pricing = self.trade_pricing_policy;
+        let opposite_book = match book_side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+
+        // Nothing to match against, skip walking the levels entirely
+        if opposite_book.is_empty() {
+            return;
+        }
+
+        // Walk the levels in place from the touch: lowest ask first for buy
+        // orders, highest bid first for sell orders
+        let (asks, bids) = match book_side {
+            Side::Buy => (None, Some(self.bids.iter_mut().rev())),
+            Side::Sell => (Some(self.asks.iter_mut()), None),
+        };
+        let mut prices_to_remove = Vec::new();
+        let mut levels_taken = 0;
+
+        for (&price_level, order_queue) in asks.into_iter().flatten().chain(bids.into_iter().flatten()) {
+            if *incoming_order.quantity == 0 || trades.len() >= rules.max_trades {
+                break;
+            }
+
+            // Check if we can match at this price level
+            let can_match = is_marketable(incoming_order.side, incoming_order.price, price_level, equal_price_crosses);
+
+            if !can_match {
+                break;
+            }
+
+            // Retained empty level, nothing to match
+            if order_queue.is_empty() {
+                continue;
+            }
+
+            if levels_taken == max_levels {
+                break;
+            }
+            levels_taken += 1;
+
+            let mut book = FillEffects {
+                order_index: &mut self.order_index,
+                events: &mut self.event_handler,
+                listener: &mut self.listener,
+                positions: &mut self.positions,
+            };
+            let trade_price = if incoming_order.price == market_limit(incoming_order.side) {
+                price_level
+            } else {
+                pricing.trade_price(incoming_order.side, price_level, incoming_order.price)
+            };
+            fill_from_level(order_queue, incoming_order, trade_price, trades, rules, &mut book);
+
+            // Removing a level needs the whole book, so it waits until the
+            // walk is done. Only levels this order emptied are collected.
+            if order_queue.is_empty() {
+                prices_to_remove.push(price_level);
+            }
+        }
+
+        // Clean up empty price levels
+        for price in prices_to_remove {
+            self.remove_level(book_side, price);
+        }
+    }
+
+    fn match_rules(&self) -> MatchRules {
+        MatchRules {
+            max_trades: self.max_trades_per_order.unwrap_or(usize::MAX),
+            discipline: self.level_discipline,
+            self_trade_policy: self.self_trade_policy,
+        }
+    }
+
+    /// Rests a fully hidden order in the dark book. It never shows up in
+    /// best prices or depth, and is only executed by incoming orders, at the
+    /// lit midpoint, before they reach the lit book. Hidden orders never
+    /// match on entry, so they rest the same way before the open, but they
+    /// take no part in the opening auction. Like any order, one without a
+    /// positive quantity doesn't rest.
+    pub fn place_hidden(&mut self, side: Side, price: i64, quantity: i32) -> Uuid {
+        let order = self.new_order(side, price.into(), quantity.into());
+        let id = order.id;
+        self.add_hidden_order_to_book(order);
+        id
+    }
+
+    /// Queues `order` in the dark book at its price. Returns false if it had
+    /// nothing to rest.
+    fn add_hidden_order_to_book(&mut self, order: Order) -> bool {
+        if *order.quantity <= 0 {
+            return false;
+        }
+        if let Some(listener) = self.listener.as_mut() {
+            listener.on_order_rested(&order);
+        }
+        emit(&mut self.event_handler, || BookEvent::HiddenOrderAdded(order.clone()));
+
+        let hidden_book = match order.side {
+            Side::Buy => &mut self.hidden_bids,
+            Side::Sell => &mut self.hidden_asks,
+        };
+        self.order_index.insert(order.id, order.price);
+        hidden_book.entry(order.price).or_default().push_back(order);
+        true
+    }
+
+    /// Executes an incoming order against hidden orders at the lit midpoint,
+    /// rounded down to a whole price. Needs both lit sides for a midpoint, and
+    /// only hidden orders whose limit allows the midpoint take part.
+    fn match_hidden(&mut self, incoming_order: &mut Order, trades: &mut Vec<Trade>) {
+        let Some(midpoint) = self.hidden_match_price(incoming_order.side, incoming_order.price) else {
+            return;
+        };
+
+        let rules = self.match_rules();
+        // Hidden orders willing to trade at the midpoint, best limit first
+        let (eligible_levels, hidden_book): (Vec<Price>, _) = match incoming_order.side {
+            Side::Buy => (self.hidden_asks.range(..=midpoint).map(|(price, _)| *price).collect(), &mut self.hidden_asks),
+            Side::Sell => (self.hidden_bids.range(midpoint..).rev().map(|(price, _)| *price).collect(), &mut self.hidden_bids),
+        };
+
+        for price in eligible_levels {
+            if *incoming_order.quantity == 0 || trades.len() >= rules.max_trades {
+                break;
+            }
+            if let Some(order_queue) = hidden_book.get_mut(&price) {
+                let mut book = FillEffects {
+                    order_index: &mut self.order_index,
+                    events: &mut self.event_handler,
+                    listener: &mut self.listener,
+                    positions: &mut self.positions,
+                };
+                fill_from_level(order_queue, incoming_order, midpoint, trades, rules, &mut book);
+                if order_queue.is_empty() {
+                    hidden_book.remove(&price);
+                }
+            }
+        }
+    }
+
+    /// Lit midpoint an incoming order at `price` would trade hidden orders
+    /// at, if there is one and the order's limit allows it
+    fn hidden_match_price(&self, side: Side, price: Price) -> Option<Price> {
+        let (best_bid, _) = self.best_buy()?;
+        let (best_ask, _) = self.best_sell()?;
+        // Widened so extreme prices can't overflow the sum
+        let midpoint = Price((i128::from(*best_bid) + i128::from(*best_ask)).div_euclid(2) as i64);
+
+        let marketable = match side {
+            Side::Buy => price >= midpoint,
+            Side::Sell => price <= midpoint,
+        };
+        marketable.then_some(midpoint)
+    }
+
+    /// Number of individual trades a limit order would generate if placed
+    /// now, without touching the book
+    pub fn trade_count_preview(&self, side: Side, price: i64, quantity: i32) -> usize {
+        self.preview_fill(side, price, quantity).0
+    }
+
+    /// Number of trades and total quantity a limit order would execute if
+    /// placed now, without touching the book. Follows the same path as
+    /// matching: hidden liquidity first, then the lit levels, within the
+    /// trade cap, with iceberg slices replenished as they fill.
+    fn preview_fill(&self, side: Side, price: i64, quantity: i32) -> (usize, i64) {
+        if self.session_state == SessionState::PreOpen || quantity <= 0 {
+            return (0, 0);
+        }
+
+        let price = Price(price);
+        let max_trades = self.max_trades_per_order.unwrap_or(usize::MAX);
+        let mut remaining = i64::from(quantity);
+        let mut trade_count = 0;
+        let mut fill_level = |orders: &VecDeque<Order>| {
+            preview_level(orders, self.level_discipline, &mut remaining, &mut trade_count, max_trades);
+        };
+
+        if let Some(midpoint) = self.hidden_match_price(side, price) {
+            match side {
+                Side::Buy => self.hidden_asks.range(..=midpoint).for_each(|(_, orders)| fill_level(orders)),
+                Side::Sell => self.hidden_bids.range(midpoint..).rev().for_each(|(_, orders)| fill_level(orders)),
+            }
+        }
+
+        let opposite_side = side.opposite();
+        self.level_queues(opposite_side)
+            .take_while(|(level_price, _)| is_marketable(side, price, *level_price, self.equal_price_crosses))
+            .for_each(|(_, orders)| fill_level(orders));
+
+        (trade_count, i64::from(quantity) - remaining)
+    }
+
+    /// Removes an emptied price level, or keeps it around empty if level
+    /// retention is configured
+    fn remove_level(&mut self, side: Side, price: Price) {
+        if self.level_retention > 0 {
+            self.retained_levels.retain(|&(retained_side, retained_price, _)| (retained_side, retained_price) != (side, price));
+            self.retained_levels.push((side, price, self.sequence + self.level_retention));
+        } else {
+            self.drop_level_if_empty(side, price);
+        }
+
+        if let Some(listener) = self.listener.as_mut() {
+            listener.on_level_cleared(side, price);
+        }
+    }
+
+    /// Drops a price level that has no orders left, keeping its queue for
+    /// reuse
+    fn drop_level_if_empty(&mut self, side: Side, price: Price) {
+        let book = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+
+        if book.get(&price).is_some_and(VecDeque::is_empty)
+            && let Some(queue) = book.remove(&price)
+        {
+            recycle_level(&mut self.spare_levels, queue);
+        }
+    }
+
+    /// Runs the opening auction and switches the book to continuous trading.
+    /// Everything that can cross trades at a single clearing price, and
+    /// whatever is left keeps its place as the opening book. Stop orders the
+    /// clearing price triggers are then entered into the open book, their
+    /// trades following the auction's. Returns the clearing price, or `None`
+    /// if nothing crossed.
+    pub fn open_from_auction(&mut self) -> (Option<Price>, Vec<Trade>) {
+        self.begin_operation();
+        let touch = self.touch();
+        let clearing_price = self.auction_clearing_price();
+        let mut trades = clearing_price
+            .map(|price| self.uncross_at(price))
+            .unwrap_or_default();
+        self.record_trades(&trades, touch);
+        self.session_state = SessionState::Open;
+        self.trigger_stops(&mut trades);
+        (clearing_price, trades)
+    }
+
+    /// Price maximizing the executable volume. Ties go to the smallest
+    /// imbalance between demand and supply, then to the lowest price.
+    fn auction_clearing_price(&self) -> Option<Price> {
+        let mut best: Option<(i64, i64, Price)> = None;
+
+        for &price in self.bids.keys().chain(self.asks.keys()) {
+            let (demand, supply) = self.auction_interest(price);
+            let matched = demand.min(supply);
+            if matched == 0 {
+                continue;
+            }
+
+            let imbalance = (demand - supply).abs();
+            let is_better = match best {
+                None => true,
+                Some((best_matched, best_imbalance, best_price)) => {
+                    matched > best_matched
+                        || (matched == best_matched && imbalance < best_imbalance)
+                        || (matched == best_matched && imbalance == best_imbalance && price < best_price)
+                }
+            };
+            if is_better {
+                best = Some((matched, imbalance, price));
+            }
+        }
+
+        best.map(|(_, _, price)| price)
+    }
+
+    /// Bid quantity willing to buy at `price` and ask quantity willing to
+    /// sell at it
+    fn auction_interest(&self, price: Price) -> (i64, i64) {
+        let demand = self.bids.range(price..).map(|(_, orders)| level_volume(orders)).sum();
+        let supply = self.asks.range(..=price).map(|(_, orders)| level_volume(orders)).sum();
+        (demand, supply)
+    }
+
+    /// Quantity that would trade if a single uniform-price auction cleared
+    /// at `price`: the smaller of the bid quantity at or above it and the ask
+    /// quantity at or below it. Read-only, for plotting supply against
+    /// demand or discovering an auction price.
+    pub fn matchable_at(&self, price: Price) -> Quantity {
+        let (demand, supply) = self.auction_interest(price);
+        let matched = demand.min(supply).clamp(0, i64::from(i32::MAX));
+        Quantity(matched as i32)
+    }
+
+    /// Crosses bids at or above `price` with asks at or below it, in priority
+    /// order, all at `price`. Within a level orders fill in the order the
+    /// level discipline picks, oldest first under pro-rata. Neither side is
+    /// the aggressor in an auction, so the sell order is reported as the
+    /// maker and the buy as the taker.
+    fn uncross_at(&mut self, price: Price) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let now = self.clock.now();
+        let discipline = self.level_discipline;
+
+        while let (Some(bid_price), Some(ask_price)) = (self.best_level_price(Side::Buy), self.best_level_price(Side::Sell)) {
+            if bid_price < price || ask_price > price {
+                break;
+            }
+
+            let (Some(bids), Some(asks)) = (self.bids.get_mut(&bid_price), self.asks.get_mut(&ask_price)) else {
+                break;
+            };
+            let (Some(bid), Some(ask)) = (next_to_fill(bids, discipline), next_to_fill(asks, discipline)) else {
+                break;
+            };
+
+            let trade_quantity = (*bid.quantity).min(*ask.quantity);
+            let trade = Trade::new(price, trade_quantity.into(), ask.id, bid.id, Side::Buy)
+                .with_timestamp(now)
+                .with_maker_owner(ask.owner)
+                .with_taker_owner(bid.owner);
+            record_position(&mut self.positions, bid.owner, ask.owner, trade_quantity);
+            emit(&mut self.event_handler, || BookEvent::TradeExecuted(trade.clone()));
+            trades.push(trade);
+            bid.quantity = (*bid.quantity - trade_quantity).into();
+            ask.quantity = (*ask.quantity - trade_quantity).into();
+            // Both orders were resting, so both are reported
+            if let Some(listener) = self.listener.as_mut() {
+                listener.on_fill(ask, trade_quantity.into());
+                listener.on_fill(bid, trade_quantity.into());
+            }
+
+            let filled = (*bid.quantity == 0, *ask.quantity == 0);
+            for (orders, filled) in [(&mut *bids, filled.0), (&mut *asks, filled.1)] {
+                if filled && let Some(mut order) = pop_next_to_fill(orders, discipline) {
+                    if order.replenish() {
+                        requeue_slice(orders, order, discipline, now, self.sequence);
+                    } else {
+                        self.order_index.remove(&order.id);
+                    }
+                }
+            }
+
+            let (bids_empty, asks_empty) = (bids.is_empty(), asks.is_empty());
+            if bids_empty {
+                self.remove_level(Side::Buy, bid_price);
+            }
+            if asks_empty {
+                self.remove_level(Side::Sell, ask_price);
+            }
+        }
+
+        trades
+    }
+
+    /// Queues `order` at its price. Returns false if the order did not stay
+    /// in the book: it had nothing left to rest, or its level was evicted to
+    /// respect the level cap.
+    fn add_order_to_book(&mut self, order: Order) -> bool {
+        // An empty order would sit in its level without ever filling
+        if *order.quantity <= 0 {
+            return false;
+        }
+        let book = match order.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+
+        if let Some(listener) = self.listener.as_mut() {
+            listener.on_order_rested(&order);
+        }
+        emit(&mut self.event_handler, || BookEvent::OrderAdded(order.clone()));
+
+        let side = order.side;
+        let price = order.price;
+        self.order_index.insert(order.id, price);
+        let spare_levels = &mut self.spare_levels;
+        book.entry(order.price)
+            .or_insert_with(|| spare_levels.pop().unwrap_or_default())
+            .push_back(order);
+
+        // Retained empty levels don't count towards the cap
+        if let Some(max_levels) = self.max_levels_per_side
+            && book.len() > max_levels
+            && book.values().filter(|orders| !orders.is_empty()).count() > max_levels
+        {
+            return self.evict_worst_level(side) != Some(price);
+        }
+        true
+    }
+
+    /// Cancels every order on the worst-priced level of `side` that has
+    /// any, and removes the level, returning its price
+    fn evict_worst_level(&mut self, side: Side) -> Option<Price> {
+        let book = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let mut levels = book.iter().filter(|(_, orders)| !orders.is_empty());
+        let worst_level = match side {
+            Side::Buy => levels.next(),
+            Side::Sell => levels.next_back(),
+        };
+        let price = *worst_level?.0;
+        let mut orders = book.remove(&price)?;
+
+        if let Some(listener) = self.listener.as_mut() {
+            listener.on_orders_evicted(side, price, orders.make_contiguous());
+        }
+        for order in &orders {
+            self.order_index.remove(&order.id);
+            emit(&mut self.event_handler, || BookEvent::OrderCancelled(order.id));
+        }
+        recycle_level(&mut self.spare_levels, orders);
+        Some(price)
+    }
+
+    /// Level queue holding the lit resting order with `id`, and its position
+    /// in that queue
+    fn find_order_mut(&mut self, id: Uuid) -> Option<(&mut VecDeque<Order>, usize)> {
+        let price = *self.order_index.get(&id)?;
+        find_in([&mut self.bids, &mut self.asks], price, id)
+    }
+
+    /// Like `find_order_mut`, but also looking through the hidden orders
+    fn find_resting_order_mut(&mut self, id: Uuid) -> Option<(&mut VecDeque<Order>, usize)> {
+        let price = *self.order_index.get(&id)?;
+        find_in([&mut self.bids, &mut self.asks, &mut self.hidden_bids, &mut self.hidden_asks], price, id)
+    }
+
+    /// Level queue holding the resting order with `id`, its price and the
+    /// order's position in the queue
+    fn locate_order(&self, id: Uuid) -> Option<(&VecDeque<Order>, Price, usize)> {
+        let price = *self.order_index.get(&id)?;
+        [&self.bids, &self.asks].into_iter().find_map(|book| {
+            let orders = book.get(&price)?;
+            let position = orders.iter().position(|order| order.id == id)?;
+            Some((orders, price, position))
+        })
+    }
+
+    /// Resting order with `id`, lit or hidden, as it currently stands in
+    /// the book
+    pub fn get_order(&self, id: Uuid) -> Option<&Order> {
+        let price = self.order_index.get(&id)?;
+        [&self.bids, &self.asks, &self.hidden_bids, &self.hidden_asks]
+            .into_iter()
+            .filter_map(|book| book.get(price))
+            .flatten()
+            .find(|order| order.id == id)
+    }
+
+    /// Cancels the resting order with `id`, lit or hidden, and returns it.
+    /// A level left without orders is removed.
+    pub fn cancel_order(&mut self, id: Uuid) -> Result<Order, OrderError> {
+        let price = *self.order_index.get(&id).ok_or(OrderError::OrderNotFound)?;
+
+        let lit = take_order(&mut self.bids, price, id).or_else(|| take_order(&mut self.asks, price, id));
+        let cancelled = if let Some(order) = lit {
+            self.drop_level_if_empty(order.side, price);
+            order
+        } else {
+            let order = take_order(&mut self.hidden_bids, price, id)
+                .or_else(|| take_order(&mut self.hidden_asks, price, id))
+                .ok_or(OrderError::OrderNotFound)?;
+            let hidden_book = match order.side {
+                Side::Buy => &mut self.hidden_bids,
+                Side::Sell => &mut self.hidden_asks,
+            };
+            if hidden_book.get(&price).is_some_and(VecDeque::is_empty) {
+                hidden_book.remove(&price);
+            }
+            order
+        };
+
+        self.order_index.remove(&id);
+        emit(&mut self.event_handler, || BookEvent::OrderCancelled(id));
+        self.begin_operation();
+        Ok(cancelled)
+    }
+
+    /// Cancels the oldest order resting at `price` on `side` and returns its
+    /// id, removing the level if that was its last order
+    pub fn cancel_front(&mut self, side: Side, price: i64) -> Option<Uuid> {
+        let price = Price(price);
+        let book = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let cancelled = book.get_mut(&price)?.pop_front()?;
+        self.order_index.remove(&cancelled.id);
+        emit(&mut self.event_handler, || BookEvent::OrderCancelled(cancelled.id));
+
+        self.begin_operation();
+        self.drop_level_if_empty(side, price);
+        Some(cancelled.id)
+    }
+
+    /// Removes every resting order, lit or hidden, that has expired by `now`
+    /// and returns them. Levels left without orders are removed. This scans
+    /// the whole book; a book holding many good-till-date orders would want
+    /// them indexed by expiry instead.
+    pub fn purge_expired(&mut self, now: u64) -> Vec<Order> {
+        self.cancel_where(|order| order.is_expired(now))
+    }
+
+    /// Cancels every resting order, lit or hidden, belonging to `owner` and
+    /// returns them, for pulling a participant's quotes at once. Levels left
+    /// without orders are removed.
+    pub fn cancel_all_for_owner(&mut self, owner: u64) -> Vec<Order> {
+        self.cancel_where(|order| order.owner == owner)
+    }
+
+    /// Cancels every resting order `cancels` picks, lit bids and asks first,
+    /// then hidden ones, each side in priority order. Scans the whole book.
+    fn cancel_where(&mut self, cancels: impl Fn(&Order) -> bool) -> Vec<Order> {
+        let mut cancelled = Vec::new();
+        let mut emptied_levels = Vec::new();
+        for (side, book) in [(Side::Buy, &mut self.bids), (Side::Sell, &mut self.asks)] {
+            let (bids, asks) = match side {
+                Side::Buy => (Some(book.iter_mut().rev()), None),
+                Side::Sell => (None, Some(book.iter_mut())),
+            };
+            for (price, orders) in bids.into_iter().flatten().chain(asks.into_iter().flatten()) {
+                if !orders.iter().any(&cancels) {
+                    continue;
+                }
+                cancelled.extend(orders.iter().filter(|order| cancels(order)).cloned());
+                orders.retain(|order| !cancels(order));
+                if orders.is_empty() {
+                    emptied_levels.push((side, *price));
+                }
+            }
+        }
+        for book in [&mut self.hidden_bids, &mut self.hidden_asks] {
+            for orders in book.values_mut() {
+                cancelled.extend(orders.iter().filter(|order| cancels(order)).cloned());
+                orders.retain(|order| !cancels(order));
+            }
+            book.retain(|_, orders| !orders.is_empty());
+        }
+
+        for (side, price) in emptied_levels {
+            self.drop_level_if_empty(side, price);
+        }
+        for order in &cancelled {
+            self.order_index.remove(&order.id);
+            emit(&mut self.event_handler, || BookEvent::OrderCancelled(order.id));
+        }
+        if !cancelled.is_empty() {
+            self.begin_operation();
+        }
+        cancelled
+    }
+
+    /// Changes the quantity of a resting order, lit or hidden. A reduction
+    /// keeps the order's place in the queue; an increase loses time priority
+    /// and sends it to the back of its level. Amending to 0 cancels the
+    /// order.
+    pub fn amend_quantity(&mut self, id: Uuid, new_qty: i32) -> Result<(), OrderError> {
+        if new_qty < 0 {
+            return Err(OrderError::NonPositiveQuantity);
+        }
+        if new_qty == 0 {
+            return self.cancel_order(id).map(|_| ());
+        }
+        let now = self.clock.now();
+        // Sequence of this amendment, taken by `begin_operation` below
+        let sequence = self.sequence + 1;
+        let (orders, position) = self.find_resting_order_mut(id).ok_or(OrderError::OrderNotFound)?;
+        let old_qty = orders[position].quantity;
+
+        if new_qty <= *old_qty {
+            orders[position].quantity = new_qty.into();
+        } else if let Some(mut order) = orders.remove(position) {
+            order.quantity = new_qty.into();
+            orders.push_back(order.with_timestamp(now).with_sequence(sequence));
+        }
+        emit(&mut self.event_handler, || BookEvent::OrderAmended { id, old_qty, new_qty: new_qty.into() });
+        self.begin_operation();
+        Ok(())
+    }
+
+    /// Splits a resting order in two at the same price. The original keeps
+    /// `first_quantity` and its place in the queue, and a new order with the
+    /// remainder is queued right behind it. Returns the new order's id. An
+    /// iceberg's reserve stays with the original; the new order is a plain
+    /// one holding the rest of the visible slice.
+    pub fn split_order(&mut self, id: Uuid, first_quantity: i32) -> Result<Uuid, SplitError> {
+        let (orders, position) = self.find_order_mut(id).ok_or(SplitError::OrderNotFound)?;
+        if first_quantity <= 0 || first_quantity >= *orders[position].quantity {
+            return Err(SplitError::InvalidQuantity);
+        }
+
+        // Only take an id once the split is known to go ahead
+        let second_id = self.id_generator.next_id();
+        let (orders, position) = self.find_order_mut(id).ok_or(SplitError::OrderNotFound)?;
+        let original = &mut orders[position];
+        let old_qty = original.quantity;
+        let mut second = original.clone();
+        second.id = second_id;
+        second.quantity = (*original.quantity - first_quantity).into();
+        second.display_size = Quantity(0);
+        second.reserve = Quantity(0);
+        original.quantity = first_quantity.into();
+
+        let price = second.price;
+        orders.insert(position + 1, second.clone());
+        self.order_index.insert(second_id, price);
+        emit(&mut self.event_handler, || BookEvent::OrderAmended { id, old_qty, new_qty: first_quantity.into() });
+        emit(&mut self.event_handler, || BookEvent::OrderAdded(second));
+        self.begin_operation();
+        Ok(second_id)
+    }
+
+    /// Debug check that every level still queues its orders in arrival
+    /// order, catching anything that re-queued an order out of turn. Does
+    /// nothing in release builds.
+    pub fn assert_priority(&self) {
+        for (price, orders) in self.bids.iter().chain(self.asks.iter()) {
+            debug_assert!(
+                orders.iter().zip(orders.iter().skip(1)).all(|(ahead, behind)| ahead.sequence <= behind.sequence),
+                "orders at {price:?} are out of priority order"
+            );
+        }
+    }
+
+    pub fn best_buy(&self) -> Option<(Price, Quantity)> {
+        // Highest bid price (last in BTreeMap)
+        self.levels(Side::Buy).next()
+    }
+
+    pub fn best_sell(&self) -> Option<(Price, Quantity)> {
+        // Lowest ask price (first in BTreeMap)
+        self.levels(Side::Sell).next()
+    }
+
+    /// Top `levels` aggregated levels of each side, bids highest first and
+    /// asks lowest first. Sides with fewer levels return what they have.
+    pub fn depth(&self, levels: usize) -> (Levels, Levels) {
+        (
+            self.levels(Side::Buy).take(levels).collect(),
+            self.levels(Side::Sell).take(levels).collect(),
+        )
+    }
+
+    /// Top `levels` of each side together with the last trade price and
+    /// the sequence number they were taken at
+    pub fn market_data(&self, levels: usize) -> MarketData {
+        let (bids, asks) = self.depth(levels);
+        MarketData { bids, asks, last_trade_price: self.last_trade_price, sequence: self.sequence }
+    }
+
+    /// Lit quantity resting at exactly `price` on `side`, 0 if there is no
+    /// such level
+    pub fn quantity_at_price(&self, side: Side, price: i64) -> Quantity {
+        let book = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        book.get(&Price(price)).map_or(Quantity(0), aggregate_quantity_at_price)
+    }
+
+    /// Number of lit orders resting at exactly `price` on `side`, 0 if there
+    /// is no such level
+    pub fn order_count_at_price(&self, side: Side, price: i64) -> usize {
+        let book = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        book.get(&Price(price)).map_or(0, VecDeque::len)
+    }
+
+    /// Top `levels` of `side`, best price first, each with the running total
+    /// of quantity from the best price down to and including it
+    pub fn cumulative_depth(&self, side: Side, levels: usize) -> Vec<(Price, Quantity, Quantity)> {
+        let mut cumulative = Quantity(0);
+        self.levels(side)
+            .take(levels)
+            .map(|(price, quantity)| {
+                cumulative = Quantity(cumulative.saturating_add(*quantity));
+                (price, quantity, cumulative)
+            })
+            .collect()
+    }
+
+    /// Order book imbalance over the top `levels` of each side:
+    /// `(bid volume - ask volume) / (bid volume + ask volume)`, from -1.0 when
+    /// asks dominate to 1.0 when bids do. `None` if either side is empty.
+    pub fn imbalance(&self, levels: usize) -> Option<f64> {
+        let volume = |side| -> i64 { self.levels(side).take(levels).map(|(_, quantity)| i64::from(*quantity)).sum() };
+        let (bid_volume, ask_volume) = (volume(Side::Buy), volume(Side::Sell));
+        if bid_volume == 0 || ask_volume == 0 {
+            return None;
+        }
+        Some((bid_volume - ask_volume) as f64 / (bid_volume + ask_volume) as f64)
+    }
+
+    /// Whether a limit order at `price` on `side` would trade on entry
+    /// against the lit book: at or through the best opposite price, or only
+    /// through it when equal prices don't cross. False if the opposite side
+    /// is empty.
+    pub fn would_cross(&self, side: Side, price: i64) -> bool {
+        let book_side = side.opposite();
+        self.best_level_price(book_side)
+            .is_some_and(|best_opposite| is_marketable(side, Price(price), best_opposite, self.equal_price_crosses))
+    }
+
+    /// Best ask minus best bid, negative while the book is crossed before
+    /// the open. `None` if either side is empty.
+    pub fn spread(&self) -> Option<Price> {
+        let (best_bid, _) = self.best_buy()?;
+        let (best_ask, _) = self.best_sell()?;
+        Some(Price(best_ask.saturating_sub(*best_bid)))
+    }
+
+    /// Mean of the best bid and ask. Both are widened to `f64` before adding,
+    /// so the result keeps the half tick and cannot overflow. `None` if
+    /// either side is empty.
+    pub fn mid_price(&self) -> Option<f64> {
+        let (best_bid, _) = self.best_buy()?;
+        let (best_ask, _) = self.best_sell()?;
+        Some((*best_bid as f64 + *best_ask as f64) / 2.0)
+    }
+
+    /// Price of the most recent trade, `None` until the book first trades
+    pub const fn last_trade_price(&self) -> Option<Price> {
+        self.last_trade_price
+    }
+
+    /// Trades executed since the book was created or stats last reset
+    pub const fn trade_count(&self) -> u64 {
+        self.trade_count
+    }
+
+    /// Quantity traded since the book was created or stats last reset
+    pub const fn total_traded_volume(&self) -> u64 {
+        self.traded_volume
+    }
+
+    /// Zeroes the trade count and traded volume, e.g. at the start of a
+    /// session
+    pub const fn reset_stats(&mut self) {
+        self.trade_count = 0;
+        self.traded_volume = 0;
+    }
+
+    /// Best bid and ask prices, `None` unless both sides have orders
+    fn touch(&self) -> Option<(Price, Price)> {
+        Some((self.best_level_price(Side::Buy)?, self.best_level_price(Side::Sell)?))
+    }
+
+    /// Best price on `side` that still has orders
+    fn best_level_price(&self, side: Side) -> Option<Price> {
+        self.level_queues(side)
+            .find(|(_, orders)| !orders.is_empty())
+            .map(|(price, _)| price)
+    }
+
+    /// Level queues of one side in priority order, best price first
+    fn level_queues(&self, side: Side) -> impl Iterator<Item = (Price, &VecDeque<Order>)> + '_ {
+        let (bids, asks) = match side {
+            Side::Buy => (Some(self.bids.iter().rev()), None),
+            Side::Sell => (None, Some(self.asks.iter())),
+        };
+        bids.into_iter()
+            .flatten()
+            .chain(asks.into_iter().flatten())
+            .map(|(price, orders)| (*price, orders))
+    }
+
+    /// Cursor over the levels of `side`, best price first, for walking the
+    /// book lazily
+    pub fn cursor(&self, side: Side) -> LevelCursor<'_> {
+        let levels = match side {
+            Side::Buy => self.bids.iter(),
+            Side::Sell => self.asks.iter(),
+        };
+        LevelCursor { side, levels }
+    }
+
+    /// Lit orders resting on `side` in the order they would fill: best price
+    /// first, then by the level discipline within each level
+    pub fn iter_side(&self, side: Side) -> impl Iterator<Item = &Order> + '_ {
+        let discipline = self.level_discipline;
+        self.level_queues(side).flat_map(move |(_, orders)| in_fill_order(orders, discipline))
+    }
+
+    /// Lit orders on both sides, bids then asks, each in fill order
+    pub fn iter_orders(&self) -> impl Iterator<Item = &Order> + '_ {
+        self.iter_side(Side::Buy).chain(self.iter_side(Side::Sell))
+    }
+
+    /// Aggregated levels of one side in priority order, best price first,
+    /// skipping retained empty levels
+    fn levels(&self, side: Side) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.level_queues(side)
+            .filter(|(_, orders)| !orders.is_empty())
+            .map(|(price, orders)| (price, aggregate_quantity_at_price(orders)))
+    }
+
+    /// Ratio of the volume-weighted distance of bids from the mid to that of
+    /// asks, over the top `levels` of each side. Below 1.0 bids sit closer to
+    /// the mid than asks, above 1.0 they sit further away. `None` if either
+    /// side is empty.
+    pub fn skew(&self, levels: usize) -> Option<f64> {
+        let mid = self.mid_price()?;
+
+        let weighted_distance = |side| {
+            let (weighted, volume) = self.levels(side).take(levels).fold(
+                (0.0, 0.0),
+                |(weighted, volume), (price, quantity)| {
+                    let quantity = f64::from(*quantity);
+                    (weighted + (*price as f64 - mid).abs() * quantity, volume + quantity)
+                },
+            );
+            (volume > 0.0).then(|| weighted / volume)
+        };
+
+        let bid_distance = weighted_distance(Side::Buy)?;
+        let ask_distance = weighted_distance(Side::Sell)?;
+        (ask_distance > 0.0).then(|| bid_distance / ask_distance)
+    }
+
+    /// Compact L2 market-data message with the top `levels` of each side,
+    /// e.g. `{"bids":[[99,30]],"asks":[[101,10]],"seq":2}`. Prices are
+    /// shown with the configured decimals and quantities in units of the
+    /// configured quantity scale.
+    pub fn to_json_l2(&self, levels: usize) -> String {
+        let mut json = String::from("{");
+        for (name, side) in [("bids", Side::Buy), ("asks", Side::Sell)] {
+            let _ = write!(json, "\"{name}\":[");
+            // Retained empty levels are published at quantity 0
+            for (i, (price, orders)) in self.level_queues(side).take(levels).enumerate() {
+                let separator = if i == 0 { "" } else { "," };
+                let price = self.display_price(price);
+                let quantity = self.display_quantity(aggregate_quantity_at_price(orders));
+                let _ = write!(json, "{separator}[{price},{quantity}]");
+            }
+            json.push_str("],");
+        }
+        let _ = write!(json, "\"seq\":{}}}", self.sequence);
+        json
+    }
+
+    /// Two-sided executable quote for `quantity`: the average price paid to
+    /// buy it by sweeping the asks, and received to sell it by sweeping the
+    /// bids. `None` if either side can't fill the full quantity.
+    pub fn two_sided_quote(&self, quantity: i32) -> Option<(f64, f64)> {
+        let buy_price = self.sweep_average_price(Side::Sell, quantity)?;
+        let sell_price = self.sweep_average_price(Side::Buy, quantity)?;
+        Some((buy_price, sell_price))
+    }
+
+    /// Price of the last level an order on `side` would reach into to fill
+    /// `quantity`, i.e. the limit needed to fill it in full right now. A buy
+    /// walks the asks, a sell the bids. `None` if the book is too thin.
+    pub fn worst_fill_price(&self, side: Side, quantity: i32) -> Option<Price> {
+        if quantity <= 0 {
+            return None;
+        }
+
+        let book_side = side.opposite();
+        let mut remaining = i64::from(quantity);
+        self.levels(book_side).find_map(|(price, level_quantity)| {
+            remaining -= i64::from(*level_quantity);
+            (remaining <= 0).then_some(price)
+        })
+    }
+
+    /// Average price of taking `quantity` from `side` in priority order, if
+    /// it holds enough
+    fn sweep_average_price(&self, side: Side, quantity: i32) -> Option<f64> {
+        let (filled, notional) = self.sweep(side, quantity);
+        (filled > 0 && filled == i64::from(quantity)).then(|| notional as f64 / filled as f64)
+    }
+
+    /// Quantity and notional of taking up to `quantity` from `side` in
+    /// priority order
+    fn sweep(&self, side: Side, quantity: i32) -> (i64, i128) {
+        let target = i64::from(quantity.max(0));
+        let mut filled = 0;
+        let mut notional = 0;
+        for (price, level_quantity) in self.levels(side) {
+            if filled == target {
+                break;
+            }
+            let take = (target - filled).min(i64::from(*level_quantity));
+            filled += take;
+            notional += i128::from(take) * i128::from(*price);
+        }
+        (filled, notional)
+    }
+
+    /// Dry run of a market order on `side` for `quantity`: how much of it
+    /// would fill against the lit book, and the quantity-weighted average
+    /// price, rounded to the nearest price unit. A buy walks the asks, a
+    /// sell the bids; a thin book gives the partial fillable quantity.
+    /// `None` if nothing would fill.
+    pub fn quote(&self, side: Side, quantity: i32) -> Option<(Quantity, Price)> {
+        let opposite_side = side.opposite();
+        let (filled, notional) = self.sweep(opposite_side, quantity);
+        if filled == 0 {
+            return None;
+        }
+        let average = (notional as f64 / filled as f64).round();
+        Some((Quantity(filled as i32), Price(average as i64)))
+    }
+
+    /// Distance from the best price on `side` to the level at which the
+    /// running volume first reaches half of the side's total. Smaller means
+    /// liquidity is packed tighter around the touch.
+    pub fn liquidity_half_distance(&self, side: Side) -> Option<Price> {
+        let (best_price, _) = self.levels(side).next()?;
+        let total: i64 = self.levels(side).map(|(_, quantity)| i64::from(*quantity)).sum();
+
+        let mut cumulative = 0;
+        self.levels(side).find_map(|(price, quantity)| {
+            cumulative += i64::from(*quantity);
+            (cumulative * 2 >= total).then(|| Price((*price - *best_price).abs()))
+        })
+    }
+
+    /// Levels on `side` holding at least `threshold`, best price first
+    pub fn walls(&self, side: Side, threshold: Quantity) -> Vec<(Price, Quantity)> {
+        self.levels(side)
+            .filter(|&(_, quantity)| quantity >= threshold)
+            .collect()
+    }
+
+    /// Total resting quantity on `side`, saturating at `i32::MAX`
+    pub fn total_volume(&self, side: Side) -> Quantity {
+        let total: i64 = self.level_queues(side).map(|(_, orders)| level_volume(orders)).sum();
+        Quantity(total.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32)
+    }
+
+    /// Number of lit orders resting on `side`
+    pub fn order_count(&self, side: Side) -> usize {
+        self.level_queues(side).map(|(_, orders)| orders.len()).sum()
+    }
+
+    /// Number of lit orders resting on both sides
+    pub fn total_order_count(&self) -> usize {
+        self.order_count(Side::Buy) + self.order_count(Side::Sell)
+    }
+
+    /// Marked-to-market value of the resting orders on `side`: the sum of
+    /// `quantity * (price - mark)` over every order, positive for orders
+    /// priced above the mark. 0 until a mark price is set. Saturates at the
+    /// `i64` bounds.
+    pub fn resting_exposure(&self, side: Side) -> i64 {
+        let Some(mark) = self.mark_price else {
+            return 0;
+        };
+
+        self.level_queues(side)
+            .map(|(price, orders)| level_volume(orders).saturating_mul(price.saturating_sub(*mark)))
+            .fold(0, i64::saturating_add)
+    }
+
+    /// Resting quantity that fills before the order with `id` at its level
+    pub fn volume_ahead(&self, id: Uuid) -> Option<Quantity> {
+        let (orders, _, position) = self.locate_order(id)?;
+        let ahead: i64 = match self.level_discipline {
+            LevelDiscipline::Fifo => orders.range(..position).map(|order| i64::from(*order.quantity)).sum(),
+            LevelDiscipline::Lifo => orders.range(position + 1..).map(|order| i64::from(*order.quantity)).sum(),
+            // Every order at the level fills alongside the others
+            LevelDiscipline::ProRata => 0,
+        };
+        Some(Quantity(ahead.min(i64::from(i32::MAX)) as i32))
+    }
+
+    /// Average quantity per trade over the last `n` trades in the history
+    pub fn expected_fill_volume_rate(&self, n: usize) -> Option<f64> {
+        let recent = self.trade_history.iter().rev().take(n);
+        let (count, volume) = recent.fold((0, 0), |(count, volume), entry| (count + 1, volume + i64::from(*entry.trade.quantity)));
+        (count > 0).then(|| volume as f64 / f64::from(count))
+    }
+
+    /// Time-and-sales view of the trade history as (sequence, price,
+    /// quantity, taker side, cumulative volume), oldest first. Cumulative
+    /// volume is the running total over the trades still in the history,
+    /// saturating at `i32::MAX`.
+    pub fn tape(&self) -> Vec<(u64, Price, Quantity, Side, Quantity)> {
+        let mut cumulative: i32 = 0;
+        self.trade_history
+            .iter()
+            .map(|HistoryEntry { sequence, trade, .. }| {
+                cumulative = cumulative.saturating_add(*trade.quantity);
+                (*sequence, trade.price, trade.quantity, trade.taker_side, Quantity(cumulative))
+            })
+            .collect()
+    }
+
+    /// Spread `owner` earned providing liquidity over the trades still in
+    /// the history: the sum of `quantity * |price - mid|` over the trades
+    /// its resting orders made, with the mid of the best bid and ask the
+    /// taker found. Rounded to the nearest price unit, halves up. Trades
+    /// made while a side of the book was empty have no mid and are left out,
+    /// as are orders without an owner.
+    pub fn maker_spread_capture(&self, owner: u64) -> i64 {
+        if owner == 0 {
+            return 0;
+        }
+        // Twice the capture, so a mid between two prices stays exact
+        let doubled: i128 = self
+            .trade_history
+            .iter()
+            .filter(|entry| entry.trade.maker_owner == owner)
+            .filter_map(|entry| {
+                let (best_bid, best_ask) = entry.touch?;
+                let distance = (2 * i128::from(*entry.trade.price) - i128::from(*best_bid) - i128::from(*best_ask)).abs();
+                Some(i128::from(*entry.trade.quantity) * distance)
+            })
+            .sum();
+        ((doubled + 1) / 2).min(i128::from(i64::MAX)) as i64
+    }
+
+    /// Estimated number of trades until the order with `id` reaches the
+    /// front of its level, from the volume ahead of it and the average fill
+    /// size over the whole trade history
+    pub fn estimated_queue_clear(&self, id: Uuid) -> Option<f64> {
+        let volume_ahead = self.volume_ahead(id)?;
+        let rate = self.expected_fill_volume_rate(self.trade_history.len())?;
+        Some(f64::from(*volume_ahead) / rate)
+    }
+
+    /// Level holding the most resting quantity on `side`. Ties go to the
+    /// level closer to the touch.
+    pub fn max_volume_level(&self, side: Side) -> Option<(Price, Quantity)> {
+        self.levels(side).fold(None, |largest, (price, quantity)| match largest {
+            Some((_, largest_quantity)) if largest_quantity >= quantity => largest,
+            _ => Some((price, quantity)),
+        })
+    }
+
+    /// Level, order and volume counts for both sides together with the top
+    /// of book and lifetime trading totals
+    pub fn stats(&self) -> BookStats {
+        let side_totals = |levels: &BTreeMap<Price, VecDeque<Order>>| {
+            levels
+                .values()
+                .filter(|level| !level.is_empty())
+                .fold((0, 0, 0), |(level_count, order_count, volume), level| {
+                    (level_count + 1, order_count + level.len(), volume + level_volume(level).unsigned_abs())
+                })
+        };
+        let (bid_levels, bid_orders, bid_volume) = side_totals(&self.bids);
+        let (ask_levels, ask_orders, ask_volume) = side_totals(&self.asks);
+
+        BookStats {
+            bid_levels,
+            ask_levels,
+            bid_orders,
+            ask_orders,
+            bid_volume,
+            ask_volume,
+            best_bid: self.best_buy(),
+            best_ask: self.best_sell(),
+            total_trades: self.trade_count,
+            total_matched_volume: self.traded_volume,
+        }
+    }
+
+    /// Ids of every resting order, lit or hidden, placed strictly before
+    /// `cutoff`: lit bids and asks first, then hidden ones, each side in
+    /// priority order
+    pub fn orders_older_than(&self, cutoff: u64) -> Vec<Uuid> {
+        let bids = self.bids.values().rev().flatten();
+        let asks = self.asks.values().flatten();
+        let hidden_bids = self.hidden_bids.values().rev().flatten();
+        let hidden_asks = self.hidden_asks.values().flatten();
+        bids.chain(asks)
+            .chain(hidden_bids)
+            .chain(hidden_asks)
+            .filter(|order| order.timestamp < cutoff)
+            .map(|order| order.id)
+            .collect()
+    }
+}
+
+/// Book settings that govern how an incoming order fills
+#[derive(Debug, Clone, Copy)]
+struct MatchRules {
+    max_trades: usize,
+    discipline: LevelDiscipline,
+    self_trade_policy: SelfTradePolicy,
+}
+
+/// Parts of the book a fill updates besides the level being matched
+struct FillEffects<'a> {
+    order_index: &'a mut HashMap<Uuid, Price>,
+    events: &'a mut Option<EventHandler>,
+    listener: &'a mut Option<Box<dyn BookListener>>,
+    positions: &'a mut HashMap<u64, i64>,
+}
+
+impl FillEffects<'_> {
+    /// Tells the listener `resting_order` just filled `filled`, with its
+    /// quantity already reduced
+    fn notify_fill(&mut self, resting_order: &Order, filled: Quantity) {
+        if let Some(listener) = self.listener.as_mut() {
+            listener.on_fill(resting_order, filled);
+        }
+    }
+
+    /// Moves a fill of `quantity` between the positions of the incoming
+    /// order's owner and `resting_owner`
+    fn settle(&mut self, incoming_order: &Order, resting_owner: u64, quantity: i32) {
+        let (buyer, seller) = match incoming_order.side {
+            Side::Buy => (incoming_order.owner, resting_owner),
+            Side::Sell => (resting_owner, incoming_order.owner),
+        };
+        record_position(self.positions, buyer, seller, quantity);
+    }
+}
+
+/// Fills `incoming_order` against a level's queue at `trade_price`, from the
+/// end the level discipline picks, until either side runs out or `trades`
+/// holds the maximum number of trades. Filled and self-trade cancelled
+/// resting orders are dropped from the order index.
+fn fill_from_level(
+    order_queue: &mut VecDeque<Order>,
+    incoming_order: &mut Order,
+    trade_price: Price,
+    trades: &mut Vec<Trade>,
+    rules: MatchRules,
+    book: &mut FillEffects<'_>,
+) {
+    if rules.discipline == LevelDiscipline::ProRata {
+        fill_pro_rata(order_queue, incoming_order, trade_price, trades, rules, book);
+        return;
+    }
+
+    while let Some(resting_order) = next_to_fill(order_queue, rules.discipline) {
+        if *incoming_order.quantity == 0 || trades.len() >= rules.max_trades {
+            break;
+        }
+
+        if incoming_order.owner != 0 && resting_order.owner == incoming_order.owner {
+            match rules.self_trade_policy {
+                SelfTradePolicy::CancelResting => {
+                    book.order_index.remove(&resting_order.id);
+                    emit(book.events, || BookEvent::OrderCancelled(resting_order.id));
+                    pop_next_to_fill(order_queue, rules.discipline);
+                    continue;
+                }
+                SelfTradePolicy::CancelIncoming => {
+                    incoming_order.quantity = Quantity(0);
+                    break;
+                }
+                SelfTradePolicy::Allow => {}
+            }
+        }
+
+        let trade_quantity = (*incoming_order.quantity).min(*resting_order.quantity);
+
+        // Matching is instantaneous, trades share the incoming order's time
+        let trade = Trade::new(
+            trade_price,
+            trade_quantity.into(),
+            resting_order.id,
+            incoming_order.id,
+            incoming_order.side,
+        )
+        .with_timestamp(incoming_order.timestamp)
+        .with_maker_owner(resting_order.owner)
+        .with_taker_owner(incoming_order.owner);
+        emit(book.events, || BookEvent::TradeExecuted(trade.clone()));
+        trades.push(trade);
+
+        incoming_order.quantity = (*incoming_order.quantity - trade_quantity).into();
+        resting_order.quantity = (*resting_order.quantity - trade_quantity).into();
+        book.notify_fill(resting_order, trade_quantity.into());
+        book.settle(incoming_order, resting_order.owner, trade_quantity);
+
+        if *resting_order.quantity == 0
+            && let Some(mut filled) = pop_next_to_fill(order_queue, rules.discipline)
+        {
+            if filled.replenish() {
+                requeue_slice(order_queue, filled, rules.discipline, incoming_order.timestamp, incoming_order.sequence);
+            } else {
+                book.order_index.remove(&filled.id);
+            }
+        }
+    }
+}
+
+/// Pro-rata counterpart of [`fill_from_level`], see
+/// [`LevelDiscipline::ProRata`] for how the incoming quantity is shared out
+fn fill_pro_rata(
+    order_queue: &mut VecDeque<Order>,
+    incoming_order: &mut Order,
+    trade_price: Price,
+    trades: &mut Vec<Trade>,
+    rules: MatchRules,
+    book: &mut FillEffects<'_>,
+) {
+    let fifo = MatchRules { discipline: LevelDiscipline::Fifo, ..rules };
+    let incoming = i64::from(*incoming_order.quantity);
+    if incoming >= level_volume(order_queue) {
+        fill_from_level(order_queue, incoming_order, trade_price, trades, fifo, book);
+        return;
+    }
+
+    // Self-trades are settled before sharing out, so the incoming order
+    // is only split across orders it may trade with
+    let owner = incoming_order.owner;
+    if owner != 0 && order_queue.iter().any(|order| order.owner == owner) {
+        match rules.self_trade_policy {
+            SelfTradePolicy::CancelResting => {
+                order_queue.retain(|order| {
+                    if order.owner != owner {
+                        return true;
+                    }
+                    book.order_index.remove(&order.id);
+                    emit(book.events, || BookEvent::OrderCancelled(order.id));
+                    false
+                });
+                if incoming >= level_volume(order_queue) {
+                    fill_from_level(order_queue, incoming_order, trade_price, trades, fifo, book);
+                    return;
+                }
+            }
+            SelfTradePolicy::CancelIncoming => {
+                incoming_order.quantity = Quantity(0);
+                return;
+            }
+            SelfTradePolicy::Allow => {}
+        }
+    }
+
+    let allocations = pro_rata_allocations(order_queue, incoming);
+    for (resting_order, &allocation) in order_queue.iter_mut().zip(&allocations) {
+        if allocation == 0 {
+            continue;
+        }
+        if trades.len() >= rules.max_trades {
+            break;
+        }
+        let trade = Trade::new(
+            trade_price,
+            Quantity(allocation as i32),
+            resting_order.id,
+            incoming_order.id,
+            incoming_order.side,
+        )
+        .with_timestamp(incoming_order.timestamp)
+        .with_maker_owner(resting_order.owner)
+        .with_taker_owner(incoming_order.owner);
+        emit(book.events, || BookEvent::TradeExecuted(trade.clone()));
+        trades.push(trade);
+
+        incoming_order.quantity = (*incoming_order.quantity - allocation as i32).into();
+        resting_order.quantity = (*resting_order.quantity - allocation as i32).into();
+        book.notify_fill(resting_order, Quantity(allocation as i32));
+        book.settle(incoming_order, resting_order.owner, allocation as i32);
+    }
+
+    let mut replenished = Vec::new();
+    order_queue.retain_mut(|order| {
+        if *order.quantity > 0 {
+            return true;
+        }
+        if order.replenish() {
+            replenished.push(order.clone());
+        } else {
+            book.order_index.remove(&order.id);
+        }
+        false
+    });
+    for filled in replenished {
+        requeue_slice(order_queue, filled, rules.discipline, incoming_order.timestamp, incoming_order.sequence);
+    }
+}
+
+/// Adds a fill of `quantity` to the buyer's position and takes it off the
+/// seller's, leaving out orders without an owner
+fn record_position(positions: &mut HashMap<u64, i64>, buyer: u64, seller: u64, quantity: i32) {
+    for (owner, change) in [(buyer, i64::from(quantity)), (seller, -i64::from(quantity))] {
+        if owner != 0 {
+            *positions.entry(owner).or_insert(0) += change;
+        }
+    }
+}
+
+/// Share of `incoming` each order of a level gets under
+/// [`LevelDiscipline::ProRata`], in queue order, for an incoming quantity
+/// smaller than the level
+fn pro_rata_allocations(order_queue: &VecDeque<Order>, incoming: i64) -> Vec<i64> {
+    let total = level_volume(order_queue);
+    let shares: Vec<(i64, i64)> = order_queue
+        .iter()
+        .map(|order| {
+            let weighted = incoming * i64::from(*order.quantity);
+            (weighted / total, weighted % total)
+        })
+        .collect();
+    let mut allocations: Vec<i64> = shares.iter().map(|&(share, _)| share).collect();
+    let leftover = incoming - allocations.iter().sum::<i64>();
+    // Stable sort, so equal remainders stay in queue order
+    let mut by_remainder: Vec<usize> = (0..shares.len()).collect();
+    by_remainder.sort_by_key(|&index| std::cmp::Reverse(shares[index].1));
+    for &index in by_remainder.iter().take(leftover as usize) {
+        allocations[index] += 1;
+    }
+    allocations
+}
+
+/// Dry run of filling `remaining` against a level, on copies of its
+/// orders' quantities: counts the trades up to `max_trades` and takes the
+/// filled quantity off `remaining`. Iceberg slices are replenished at the
+/// end of the level that fills last, the way matching does it, and
+/// self-trades aren't considered.
+fn preview_level(orders: &VecDeque<Order>, discipline: LevelDiscipline, remaining: &mut i64, trade_count: &mut usize, max_trades: usize) {
+    if *remaining == 0 || orders.is_empty() {
+        return;
+    }
+    if discipline == LevelDiscipline::ProRata && *remaining < level_volume(orders) {
+        for allocation in pro_rata_allocations(orders, *remaining).into_iter().filter(|&allocation| allocation > 0) {
+            if *trade_count >= max_trades {
+                break;
+            }
+            *remaining -= allocation;
+            *trade_count += 1;
+        }
+        return;
+    }
+
+    // Visible quantity, reserve and slice size of each order
+    let mut queue: VecDeque<(i64, i64, i64)> = orders
+        .iter()
+        .map(|order| (i64::from(*order.quantity), i64::from(*order.reserve), i64::from(*order.display_size)))
+        .collect();
+    while *remaining > 0 && *trade_count < max_trades {
+        let Some((quantity, _, _)) = next_to_fill(&mut queue, discipline) else {
+            break;
+        };
+        let take = (*remaining).min(*quantity);
+        *remaining -= take;
+        *quantity -= take;
+        *trade_count += 1;
+
+        if *quantity == 0
+            && let Some((_, reserve, display_size)) = pop_next_to_fill(&mut queue, discipline)
+            && reserve > 0
+        {
+            let slice = reserve.min(display_size);
+            push_last_to_fill(&mut queue, (slice, reserve - slice, display_size), discipline);
+        }
+    }
+}
+
+/// Entry of a level that `discipline` fills next: the oldest order, or the
+/// newest under LIFO. Pro-rata levels fill oldest first when taken whole.
+fn next_to_fill<T>(queue: &mut VecDeque<T>, discipline: LevelDiscipline) -> Option<&mut T> {
+    match discipline {
+        LevelDiscipline::Fifo | LevelDiscipline::ProRata => queue.front_mut(),
+        LevelDiscipline::Lifo => queue.back_mut(),
+    }
+}
+
+/// Removes the entry `next_to_fill` picks
+fn pop_next_to_fill<T>(queue: &mut VecDeque<T>, discipline: LevelDiscipline) -> Option<T> {
+    match discipline {
+        LevelDiscipline::Fifo | LevelDiscipline::ProRata => queue.pop_front(),
+        LevelDiscipline::Lifo => queue.pop_back(),
+    }
+}
+
+/// Queues an entry at the end of a level that `discipline` fills last
+fn push_last_to_fill<T>(queue: &mut VecDeque<T>, entry: T, discipline: LevelDiscipline) {
+    match discipline {
+        LevelDiscipline::Fifo | LevelDiscipline::ProRata => queue.push_back(entry),
+        LevelDiscipline::Lifo => queue.push_front(entry),
+    }
+}
+
+/// Queues an iceberg's next slice, which loses time priority: it goes
+/// where `discipline` fills last, stamped as arriving at `timestamp` with
+/// `sequence`. Under LIFO that is the front, where it takes the oldest
+/// order's sequence instead so the queue stays in arrival order.
+fn requeue_slice(order_queue: &mut VecDeque<Order>, slice: Order, discipline: LevelDiscipline, timestamp: u64, sequence: u64) {
+    let sequence = match discipline {
+        LevelDiscipline::Fifo | LevelDiscipline::ProRata => sequence,
+        LevelDiscipline::Lifo => order_queue.front().map_or(sequence, |oldest| oldest.sequence.min(sequence)),
+    };
+    push_last_to_fill(order_queue, slice.with_timestamp(timestamp).with_sequence(sequence), discipline);
+}
+
+/// `value / divisor` written out with exactly `decimals` decimal places,
+/// rounded half away from zero. Works on integers throughout, so large
+/// values keep every digit.
+fn fixed_point(value: i128, divisor: i128, decimals: u32) -> String {
+    let unit = 10i128.pow(decimals);
+    let scaled = value * unit;
+    let mut units = scaled / divisor;
+    if (scaled % divisor).abs() * 2 >= divisor {
+        units += scaled.signum();
+    }
+
+    let sign = if units < 0 { "-" } else { "" };
+    let (whole, fraction) = (units.abs() / unit, units.abs() % unit);
+    if decimals == 0 {
+        format!("{sign}{whole}")
+    } else {
+        format!("{sign}{whole}.{fraction:0width$}", width = decimals as usize)
+    }
+}
+
+/// Keeps an emptied level queue for reuse, unless the pool is full
+fn recycle_level(spare_levels: &mut Vec<VecDeque<Order>>, mut queue: VecDeque<Order>) {
+    if spare_levels.len() >= SPARE_LEVEL_LIMIT {
+        return;
+    }
+    queue.clear();
+    if queue.capacity() > SPARE_LEVEL_MAX_CAPACITY {
+        queue.shrink_to(RESERVED_LEVEL_CAPACITY);
+    }
+    spare_levels.push(queue);
+}
+
+/// Level queue at `price` among `books` holding the order with `id`, and
+/// the order's position in it
+fn find_in<const N: usize>(books: [&mut BTreeMap<Price, VecDeque<Order>>; N], price: Price, id: Uuid) -> Option<(&mut VecDeque<Order>, usize)> {
+    books.into_iter().find_map(|book| {
+        let orders = book.get_mut(&price)?;
+        let position = orders.iter().position(|order| order.id == id)?;
+        Some((orders, position))
+    })
+}
+
+/// Removes the order with `id` from the level at `price` in `book`, if
+/// it rests there
+fn take_order(book: &mut BTreeMap<Price, VecDeque<Order>>, price: Price, id: Uuid) -> Option<Order> {
+    let orders = book.get_mut(&price)?;
+    let position = orders.iter().position(|order| order.id == id)?;
+    orders.remove(position)
+}
+
+/// Limit that makes an order on `side` marketable against every level, for
+/// market orders
+fn market_limit(side: Side) -> Price {
+    match side {
+        Side::Buy => Price(i64::MAX),
+        Side::Sell => Price(i64::MIN),
+    }
+}
+
+/// Whether an incoming order on `side` limited at `limit` can trade against
+/// a resting level at `level_price`
+fn is_marketable(side: Side, limit: Price, level_price: Price, equal_price_crosses: bool) -> bool {
+    match side {
+        Side::Buy => limit > level_price || (equal_price_crosses && limit == level_price),
+        Side::Sell => limit < level_price || (equal_price_crosses && limit == level_price),
+    }
+}
+
+/// Orders of a level in the sequence `discipline` fills them
+fn in_fill_order(orders: &VecDeque<Order>, discipline: LevelDiscipline) -> impl Iterator<Item = &Order> {
+    let (oldest_first, newest_first) = match discipline {
+        LevelDiscipline::Fifo | LevelDiscipline::ProRata => (Some(orders.iter()), None),
+        LevelDiscipline::Lifo => (None, Some(orders.iter().rev())),
+    };
+    oldest_first
+        .into_iter()
+        .flatten()
+        .chain(newest_first.into_iter().flatten())
+}
+
+/// Total quantity at a level, saturating at `i32::MAX` for levels holding
+/// more than a `Quantity` can represent. `level_volume` has the exact total.
+fn aggregate_quantity_at_price(orders: &VecDeque<Order>) -> Quantity {
+    let total = level_volume(orders).clamp(i64::from(i32::MIN), i64::from(i32::MAX));
+    Quantity(total as i32)
+}
+
+/// Copies of the orders of `levels`, level by level in queue order
+fn queued_orders<'a>(levels: impl Iterator<Item = &'a VecDeque<Order>>) -> Vec<Order> {
+    levels.flatten().cloned().collect()
+}
+
+/// Total quantity at a level, widened so it can be summed across levels
+fn level_volume(orders: &VecDeque<Order>) -> i64 {
+    orders.iter().map(|order| i64::from(*order.quantity)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::ManualClock;
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Listener that records every notification for later inspection
+    #[derive(Debug, Default, Clone)]
+    struct RecordingListener {
+        cleared_levels: Rc<RefCell<Vec<(Side, Price)>>>,
+        rested_orders: Rc<RefCell<Vec<Order>>>,
+        evicted_orders: Rc<RefCell<Vec<Uuid>>>,
+        /// Resting order, quantity filled and quantity left, per fill
+        resting_fills: Rc<RefCell<Vec<(Uuid, Quantity, Quantity)>>>,
+    }
+
+    /// Price and quantity of each trade, in order
+    fn fills(trades: &[Trade]) -> Vec<(i64, i32)> {
+        trades.iter().map(|t| (*t.price, *t.quantity)).collect()
+    }
+
+    impl BookListener for RecordingListener {
+        fn on_level_cleared(&mut self, side: Side, price: Price) {
+            self.cleared_levels.borrow_mut().push((side, price));
+        }
+
+        fn on_order_rested(&mut self, order: &Order) {
+            self.rested_orders.borrow_mut().push(order.clone());
+        }
+
+        fn on_orders_evicted(&mut self, _side: Side, _price: Price, orders: &[Order]) {
+            self.evicted_orders.borrow_mut().extend(orders.iter().map(|order| order.id));
+        }
+
+        fn on_fill(&mut self, order: &Order, filled: Quantity) {
+            self.resting_fills.borrow_mut().push((order.id, filled, order.quantity));
+        }
+    }
+
+    #[test]
+    fn test_empty_order_book() {
+        let book = OrderBook::new();
+        assert_eq!(book.best_buy(), None);
+        assert_eq!(book.best_sell(), None);
+    }
+
+    #[test]
+    fn test_empty_book_operations() {
+        let mut book = OrderBook::new();
+
+        // Querying an empty book must not create any levels
+        assert_eq!(book.best_buy(), None);
+        assert_eq!(book.best_sell(), None);
+        assert!(book.bids.is_empty());
+        assert!(book.asks.is_empty());
+
+        // An order into an empty book rests without trading
+        let trades = book.place_order(Side::Buy, 100, 10).trades;
+        assert!(trades.is_empty());
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(10))));
+        assert_eq!(book.best_sell(), None);
+    }
+
+    #[test]
+    fn test_reserve_does_not_change_state() {
+        let mut book = OrderBook::new();
+        book.reserve(16);
+        assert_eq!(book.best_buy(), None);
+        assert_eq!(book.best_sell(), None);
+
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Buy, 99, 20);
+        book.reserve(64);
+        assert_eq!(book.best_buy(), Some((Price(99), Quantity(20))));
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(10))));
+
+        // Matching behaves the same with recycled level queues
+        let trades = book.place_order(Side::Buy, 101, 10).trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(book.best_sell(), None);
+        book.place_order(Side::Sell, 102, 5);
+        assert_eq!(book.best_sell(), Some((Price(102), Quantity(5))));
+    }
+
+    #[test]
+    fn test_simple_buy_sell_match() {
+        let mut book = OrderBook::new();
+
+        // Add sell order first
+        let trades = book.place_order(Side::Sell, 100, 50).trades;
+        assert!(trades.is_empty());
+
+        // Add matching buy order
+        let trades = book.place_order(Side::Buy, 100, 30).trades;
+        assert_eq!(trades.len(), 1);
+
+        let trade = &trades[0];
+        assert_eq!(*trade.price, 100);
+        assert_eq!(*trade.quantity, 30);
+        assert_ne!(trade.maker_id, trade.taker_id);
+
+        // Check remaining sell order
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(20))));
+    }
+
+    #[test]
+    fn test_partial_fill_and_remainder() {
+        let mut book = OrderBook::new();
+
+        // Add sell order
+        book.place_order(Side::Sell, 100, 30);
+
+        // Add larger buy order
+        let trades = book.place_order(Side::Buy, 105, 50).trades;
+        assert_eq!(trades.len(), 1);
+
+        let trade = &trades[0];
+        assert_eq!(*trade.price, 100); // Trade at resting order price
+        assert_eq!(*trade.quantity, 30);
+
+        // Check remainder buy order was added
+        assert_eq!(book.best_buy(), Some((Price(105), Quantity(20))));
+        assert_eq!(book.best_sell(), None);
+    }
+
+    #[test]
+    fn test_price_time_priority() {
+        let mut book = OrderBook::new();
+
+        // Add multiple sell orders at same price
+        book.place_order(Side::Sell, 100, 10); // First (oldest)
+        book.place_order(Side::Sell, 100, 20); // Second
+        book.place_order(Side::Sell, 100, 15); // Third
+
+        // Buy order that matches all
+        let trades = book.place_order(Side::Buy, 100, 45).trades;
+        assert_eq!(trades.len(), 3);
+
+        // Check time priority - oldest order first
+        assert_eq!(*trades[0].quantity, 10);
+        assert_eq!(*trades[1].quantity, 20);
+        assert_eq!(*trades[2].quantity, 15);
+    }
+
+    #[test]
+    fn test_price_priority() {
+        let mut book = OrderBook::new();
+
+        // Add buy orders at different prices
+        book.place_order(Side::Buy, 98, 10);   // Lower price
+        book.place_order(Side::Buy, 102, 15);  // Higher price (best)
+        book.place_order(Side::Buy, 100, 20);  // Middle price
+
+        // Sell order matches with highest bid first
+        let trades = book.place_order(Side::Sell, 98, 50).trades;
+        assert_eq!(trades.len(), 3);
+
+        // Check price priority - highest bid first
+        assert_eq!(*trades[0].price, 102);
+        assert_eq!(*trades[1].price, 100);
+        assert_eq!(*trades[2].price, 98);
+    }
+
+    #[test]
+    fn test_no_match_different_prices() {
+        let mut book = OrderBook::new();
+
+        // Add sell order at high price
+        book.place_order(Side::Sell, 105, 10);
+
+        // Add buy order at lower price - no match
+        let trades = book.place_order(Side::Buy, 95, 10).trades;
+        assert!(trades.is_empty());
+
+        // Both orders should remain in book
+        assert_eq!(book.best_buy(), Some((Price(95), Quantity(10))));
+        assert_eq!(book.best_sell(), Some((Price(105), Quantity(10))));
+    }
+
+    #[test]
+    fn test_multiple_price_levels() {
+        let mut book = OrderBook::new();
+
+        // Build order book with multiple levels
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 15);
+        book.place_order(Side::Sell, 103, 20);
+
+        book.place_order(Side::Buy, 99, 10);
+        book.place_order(Side::Buy, 98, 15);
+
+        // Large buy order crosses spread
+        let trades = book.place_order(Side::Buy, 102, 30).trades;
+        assert_eq!(trades.len(), 2);
+
+        // Should match 101 level completely, then completely match 102 level
+        assert_eq!(*trades[0].price, 101);
+        assert_eq!(*trades[0].quantity, 10);
+        assert_eq!(*trades[1].price, 102);
+        assert_eq!(*trades[1].quantity, 15);
+
+        // Check remaining book state
+        assert_eq!(book.best_sell(), Some((Price(103), Quantity(20)))); // 103 level remains untouched
+        assert_eq!(book.best_buy(), Some((Price(102), Quantity(5)))); // remainder of incoming buy order
+    }
+
+    #[test]
+    fn test_best_buy_sell_aggregation() {
+        let mut book = OrderBook::new();
+
+        // Add multiple orders at same price level
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 100, 20);
+        book.place_order(Side::Buy, 100, 15);
+
+        book.place_order(Side::Sell, 105, 25);
+        book.place_order(Side::Sell, 105, 30);
+
+        // Check aggregated quantities
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(45)))); // 10 + 20 + 15
+        assert_eq!(book.best_sell(), Some((Price(105), Quantity(55)))); // 25 + 30
+    }
+
+    #[test]
+    fn test_realistic_trading_scenario() {
+        let mut book = OrderBook::new();
+
+        // Build realistic order book
+        // Sell side
+        book.place_order(Side::Sell, 105, 100);
+        book.place_order(Side::Sell, 104, 200);
+        book.place_order(Side::Sell, 103, 150);
+
+        // Buy side
+        book.place_order(Side::Buy, 102, 180);
+        book.place_order(Side::Buy, 101, 220);
+        book.place_order(Side::Buy, 100, 300);
+
+        // Spread should be 102 bid, 103 ask
+        assert_eq!(book.best_buy(), Some((Price(102), Quantity(180))));
+        assert_eq!(book.best_sell(), Some((Price(103), Quantity(150))));
+
+        // Large market order crosses spread
+        let trades = book.place_order(Side::Buy, 106, 500).trades;
+
+        // Should execute against all ask levels
+        assert_eq!(trades.len(), 3);
+
+        let total_traded: i32 = trades.iter().map(|t| *t.quantity).sum();
+        assert_eq!(total_traded, 450); // 150 + 200 + 100
+
+        // Check final state - buy order remainder should be in book
+        assert_eq!(book.best_buy(), Some((Price(106), Quantity(50)))); // 500 - 450 = 50 remaining
+    }
+
+    #[test]
+    fn test_orders_older_than() {
+        let clock = ManualClock::new(1_000);
+        let mut book = OrderBook::new().with_clock(clock.clone());
+
+        book.place_order(Side::Buy, 99, 10);
+        let old_bid = book.bids[&Price(99)][0].id;
+        clock.set(2_000);
+        book.place_order(Side::Sell, 105, 10);
+        let old_ask = book.asks[&Price(105)][0].id;
+        clock.set(3_000);
+        book.place_order(Side::Buy, 98, 10);
+        book.place_order(Side::Sell, 106, 10);
+
+        assert!(book.orders_older_than(1_000).is_empty());
+        assert_eq!(book.orders_older_than(1_001), vec![old_bid]);
+        assert_eq!(book.orders_older_than(3_000), vec![old_bid, old_ask]);
+        assert_eq!(book.orders_older_than(u64::MAX).len(), 4);
+
+        // Hidden orders age like lit ones
+        let hidden = book.place_hidden(Side::Sell, 110, 10);
+        clock.set(4_000);
+        let lit: Vec<Uuid> = book.iter_orders().map(|order| order.id).collect();
+        assert_eq!(book.orders_older_than(4_000), [lit, vec![hidden]].concat());
+    }
+
+    #[test]
+    fn test_incoming_quantity_exceeds_book_liquidity() {
+        let mut book = OrderBook::new();
+
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 20);
+        book.place_order(Side::Sell, 103, 30);
+        book.place_order(Side::Buy, 99, 40);
+
+        // Buy priced above every ask and larger than the whole ask side
+        let trades = book.place_order(Side::Buy, 110, 100).trades;
+        assert_eq!(trades.len(), 3);
+        let total_traded: i32 = trades.iter().map(|t| *t.quantity).sum();
+        assert_eq!(total_traded, 60);
+
+        // Ask side fully consumed, remainder rests as the new best bid
+        assert_eq!(book.best_sell(), None);
+        assert!(book.asks.is_empty());
+        assert_eq!(book.best_buy(), Some((Price(110), Quantity(40))));
+    }
+
+    #[test]
+    fn test_level_cleared_listener() {
+        let listener = RecordingListener::default();
+        let mut book = OrderBook::new().with_listener(listener.clone());
+
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 101, 5);
+        book.place_order(Side::Sell, 102, 20);
+        book.place_order(Side::Sell, 103, 30);
+        assert!(listener.cleared_levels.borrow().is_empty());
+
+        // Sweep clears 101 and 102 and partially fills 103
+        book.place_order(Side::Buy, 103, 40);
+        assert_eq!(
+            *listener.cleared_levels.borrow(),
+            vec![(Side::Sell, Price(101)), (Side::Sell, Price(102))]
+        );
+
+        // Clearing the rest of 103 reports that level once
+        book.place_order(Side::Buy, 103, 25);
+        assert_eq!(listener.cleared_levels.borrow().len(), 3);
+        assert_eq!(listener.cleared_levels.borrow()[2], (Side::Sell, Price(103)));
+    }
+
+    #[test]
+    fn test_open_from_auction() {
+        let mut book = OrderBook::new().with_session_state(SessionState::PreOpen);
+
+        book.place_order(Side::Buy, 102, 10);
+        book.place_order(Side::Buy, 101, 20);
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Sell, 99, 15);
+        book.place_order(Side::Sell, 100, 10);
+        let trades = book.place_order(Side::Sell, 103, 10).trades;
+
+        // Nothing matches before the open, so the book sits crossed
+        assert!(trades.is_empty());
+        assert_eq!(book.best_buy(), Some((Price(102), Quantity(10))));
+        assert_eq!(book.best_sell(), Some((Price(99), Quantity(15))));
+
+        // 25 can trade at both 100 and 101, 101 leaves the smaller imbalance
+        let (clearing_price, trades) = book.open_from_auction();
+        assert_eq!(clearing_price, Some(Price(101)));
+        assert_eq!(book.session_state(), SessionState::Open);
+        assert!(trades.iter().all(|t| *t.price == 101));
+        let quantities: Vec<i32> = trades.iter().map(|t| *t.quantity).collect();
+        assert_eq!(quantities, vec![10, 5, 10]);
+
+        // Residual book rests as the opening continuous book
+        assert_eq!(book.best_buy(), Some((Price(101), Quantity(5))));
+        assert_eq!(book.best_sell(), Some((Price(103), Quantity(10))));
+
+        // And incoming orders now match immediately
+        let trades = book.place_order(Side::Sell, 101, 5).trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(10))));
+    }
+
+    #[test]
+    fn test_open_from_auction_without_cross() {
+        let mut book = OrderBook::new().with_session_state(SessionState::PreOpen);
+        book.place_order(Side::Buy, 99, 10);
+        book.place_order(Side::Sell, 101, 10);
+
+        let (clearing_price, trades) = book.open_from_auction();
+        assert_eq!(clearing_price, None);
+        assert!(trades.is_empty());
+        assert_eq!(book.session_state(), SessionState::Open);
+        assert_eq!(book.best_buy(), Some((Price(99), Quantity(10))));
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(10))));
+    }
+
+    #[test]
+    fn test_split_order() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 100, 30);
+        book.place_order(Side::Sell, 100, 5);
+        let id = book.asks[&Price(100)][1].id;
+
+        let second_id = book.split_order(id, 12).unwrap();
+
+        // Pieces sit next to each other where the original was
+        let level: Vec<(Uuid, i32)> = book.asks[&Price(100)].iter().map(|o| (o.id, *o.quantity)).collect();
+        assert_eq!(level.len(), 4);
+        assert_eq!(level[1], (id, 12));
+        assert_eq!(level[2], (second_id, 18));
+        assert_eq!(level[3].1, 5);
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(45))));
+
+        // Rejections leave the book untouched
+        assert_eq!(book.split_order(id, 12), Err(SplitError::InvalidQuantity));
+        assert_eq!(book.split_order(id, 0), Err(SplitError::InvalidQuantity));
+        assert_eq!(book.split_order(Uuid::new_v4(), 1), Err(SplitError::OrderNotFound));
+        assert_eq!(book.asks[&Price(100)].len(), 4);
+    }
+
+    #[test]
+    fn test_split_iceberg_keeps_total_quantity() {
+        let mut book = OrderBook::new();
+        let id = book.place_iceberg(Side::Sell, 100, 30, 10).order_id;
+
+        let second_id = book.split_order(id, 4).unwrap();
+        assert_eq!(book.get_order(id).map(|order| (*order.quantity, *order.reserve)), Some((4, 20)));
+        assert_eq!(book.get_order(second_id).map(|order| (*order.quantity, *order.reserve)), Some((6, 0)));
+
+        // Splitting doesn't create quantity: only the original 30 can fill
+        let trades = book.place_market_order(Side::Buy, 100);
+        assert_eq!(trades.iter().map(|trade| *trade.quantity).sum::<i32>(), 30);
+        assert_eq!(book.best_sell(), None);
+    }
+
+    #[test]
+    fn test_remainder_rest_policy() {
+        let build_book = |policy| {
+            let mut book = OrderBook::new().with_remainder_rest_policy(policy);
+            book.place_order(Side::Sell, 100, 10);
+            book.place_order(Side::Sell, 101, 10);
+            book.place_order(Side::Sell, 107, 10);
+            book
+        };
+
+        // Default rests the remainder at its limit
+        let mut book = build_book(RemainderRestPolicy::AtLimit);
+        let trades = book.place_order(Side::Buy, 105, 30).trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(book.best_buy(), Some((Price(105), Quantity(10))));
+
+        // Alternative rests it at the last level it cleared
+        let mut book = build_book(RemainderRestPolicy::AtLastFillPrice);
+        let trades = book.place_order(Side::Buy, 105, 30).trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(book.best_buy(), Some((Price(101), Quantity(10))));
+        assert_eq!(book.best_sell(), Some((Price(107), Quantity(10))));
+
+        // Orders that never traded still rest at their limit
+        book.place_order(Side::Buy, 99, 5);
+        assert_eq!(book.bids[&Price(99)].len(), 1);
+    }
+
+    #[test]
+    fn test_skew() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.skew(5), None);
+
+        // Bids cluster at the touch, asks sit further out, mid is 100
+        book.place_order(Side::Buy, 99, 30);
+        book.place_order(Side::Buy, 98, 10);
+        assert_eq!(book.skew(5), None);
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 105, 30);
+
+        // Bid distance (30 * 1 + 10 * 2) / 40 = 1.25, ask (10 * 1 + 30 * 5) / 40 = 4
+        let skew = book.skew(5).unwrap();
+        assert!((skew - 1.25 / 4.0).abs() < 1e-9);
+        assert!(skew < 1.0);
+
+        // Only the touch on each side is symmetric
+        let skew = book.skew(1).unwrap();
+        assert!((skew - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_to_json_l2() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.to_json_l2(5), r#"{"bids":[],"asks":[],"seq":0}"#);
+
+        book.place_order(Side::Buy, 98, 10);
+        book.place_order(Side::Buy, 99, 30);
+        book.place_order(Side::Buy, 97, 5);
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 20);
+        book.place_order(Side::Sell, 101, 15);
+
+        assert_eq!(
+            book.to_json_l2(2),
+            r#"{"bids":[[99,30],[98,10]],"asks":[[101,25],[102,20]],"seq":6}"#
+        );
+    }
+
+    #[test]
+    fn test_run_scenario() {
+        let trades = OrderBook::run_scenario(&[
+            (Side::Sell, 101, 10),
+            (Side::Sell, 102, 20),
+            (Side::Buy, 99, 15),
+            (Side::Buy, 102, 25), // Sweeps 101 and part of 102
+            (Side::Sell, 98, 20), // Hits the 99 bid, remainder rests at 98
+            (Side::Buy, 98, 5),
+        ]);
+
+        let fills: Vec<(i64, i32)> = trades.iter().map(|t| (*t.price, *t.quantity)).collect();
+        assert_eq!(fills, vec![(101, 10), (102, 15), (99, 15), (98, 5)]);
+    }
+
+    #[test]
+    fn test_place_sliced() {
+        let mut book = OrderBook::new().with_max_order_quantity(100);
+        book.place_order(Side::Sell, 100, 120);
+        book.place_order(Side::Sell, 101, 60);
+
+        let (child_ids, trades) = book.place_sliced(Side::Buy, 101, 250);
+        assert_eq!(child_ids.len(), 3);
+
+        // Children of 100, 100 and 50 fill 120 at 100 then 60 at 101
+        let fills: Vec<(i64, i32)> = trades.iter().map(|t| (*t.price, *t.quantity)).collect();
+        assert_eq!(fills, vec![(100, 100), (100, 20), (101, 60)]);
+        assert_eq!(trades[0].taker_id, child_ids[0]);
+        assert_eq!(trades[2].taker_id, child_ids[1]);
+
+        // Second child rests its last 20, the third never traded and rests whole
+        assert_eq!(book.best_sell(), None);
+        assert_eq!(book.best_buy(), Some((Price(101), Quantity(70))));
+        assert_eq!(book.bids[&Price(101)].len(), 2);
+        assert_eq!(book.bids[&Price(101)][1].id, child_ids[2]);
+    }
+
+    #[test]
+    fn test_try_place_order_enforces_max_quantity() {
+        let mut book = OrderBook::new().with_max_order_quantity(100);
+        assert_eq!(book.try_place_order(Side::Buy, 100, 101), Err(OrderError::QuantityAboveMax));
+        assert_eq!(book.best_buy(), None);
+
+        assert!(book.try_place_order(Side::Buy, 100, 100).is_ok());
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(100))));
+    }
+
+    #[test]
+    fn test_priority_integrity() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 100, 20);
+        book.place_order(Side::Buy, 100, 30);
+        book.place_order(Side::Sell, 105, 10);
+        book.assert_priority();
+
+        // Splitting and partial fills must keep every queue in arrival order
+        let id = book.bids[&Price(100)][1].id;
+        book.split_order(id, 5).unwrap();
+        book.place_order(Side::Sell, 100, 12);
+        book.place_order(Side::Buy, 100, 7);
+        book.assert_priority();
+
+        let sequences: Vec<u64> = book.bids[&Price(100)].iter().map(|o| o.sequence).collect();
+        assert!(sequences.is_sorted());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "out of priority order")]
+    fn test_priority_violation_detected() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 100, 20);
+
+        // Corrupt the queue directly
+        book.bids.get_mut(&Price(100)).unwrap().swap(0, 1);
+        book.assert_priority();
+    }
+
+    #[test]
+    fn test_quantity_scale_in_output() {
+        let mut book = OrderBook::new().with_quantity_scale(100);
+        book.place_order(Side::Buy, 99, 500);
+        book.place_order(Side::Sell, 101, 250);
+
+        assert_eq!(book.to_json_l2(1), r#"{"bids":[[99,5.00]],"asks":[[101,2.50]],"seq":2}"#);
+
+        // Storage stays unscaled
+        assert_eq!(book.best_buy(), Some((Price(99), Quantity(500))));
+
+        // Scales other than powers of ten round to as many decimals
+        let mut book = OrderBook::new().with_quantity_scale(3);
+        book.place_order(Side::Buy, 99, 5);
+        assert_eq!(book.to_json_l2(1), r#"{"bids":[[99,1.7]],"asks":[],"seq":1}"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "quantity scale must be positive")]
+    fn test_quantity_scale_of_zero_is_refused() {
+        let _ = OrderBook::new().with_quantity_scale(0);
+    }
+
+    #[test]
+    fn test_price_decimals_in_output() {
+        let mut book = OrderBook::new().with_price_decimals(2);
+        book.place_order(Side::Buy, 10_050, 5);
+        book.place_order(Side::Sell, 10_075, 5);
+        book.place_order(Side::Sell, 10_100, 5);
+
+        assert_eq!(book.to_json_l2(2), r#"{"bids":[[100.50,5]],"asks":[[100.75,5],[101.00,5]],"seq":3}"#);
+
+        // Matching is on the fixed-point integers
+        let trades = book.place_order(Side::Buy, 10_075, 5).trades;
+        assert_eq!(fills(&trades), vec![(10_075, 5)]);
+    }
+
+    #[test]
+    fn test_max_volume_level() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.max_volume_level(Side::Buy), None);
+
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 99, 25);
+        book.place_order(Side::Buy, 99, 20);
+        book.place_order(Side::Buy, 98, 30);
+        book.place_order(Side::Sell, 105, 5);
+
+        // Middle level beats both the best and the worst
+        assert_eq!(book.max_volume_level(Side::Buy), Some((Price(99), Quantity(45))));
+        assert_eq!(book.max_volume_level(Side::Sell), Some((Price(105), Quantity(5))));
+    }
+
+    #[test]
+    fn test_replay_verified() {
+        let commands = [
+            Command::Place { side: Side::Sell, price: 101, quantity: 10 },
+            Command::Place { side: Side::Sell, price: 102, quantity: 10 },
+            Command::Place { side: Side::Buy, price: 102, quantity: 15 },
+            Command::Place { side: Side::Sell, price: 100, quantity: 5 },
+        ];
+        let fill = |price: i32, quantity: i32| Trade::new(price.into(), quantity.into(), Uuid::nil(), Uuid::nil(), Side::Buy);
+
+        let book = OrderBook::replay_verified(&commands, &[fill(101, 10), fill(102, 5)]).unwrap();
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(5))));
+
+        // Wrong quantity on the second trade
+        let diverged = OrderBook::replay_verified(&commands, &[fill(101, 10), fill(102, 6)]);
+        assert_eq!(diverged.unwrap_err(), 1);
+
+        // Expecting a trade that never happens
+        let diverged = OrderBook::replay_verified(&commands, &[fill(101, 10), fill(102, 5), fill(100, 5)]);
+        assert_eq!(diverged.unwrap_err(), 2);
+
+        // An unexpected first trade
+        assert_eq!(OrderBook::replay_verified(&commands, &[]).unwrap_err(), 0);
+    }
+
+    #[test]
+    fn test_two_sided_quote() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 99, 10);
+        book.place_order(Side::Buy, 98, 20);
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 20);
+
+        // 20 buys 10 @ 101 + 10 @ 102, sells 10 @ 99 + 10 @ 98
+        let (buy_price, sell_price) = book.two_sided_quote(20).unwrap();
+        assert!((buy_price - 101.5).abs() < 1e-9);
+        assert!((sell_price - 98.5).abs() < 1e-9);
+
+        // Symmetric book brackets the mid of 100 evenly
+        assert!(sell_price < 100.0 && 100.0 < buy_price);
+        assert!(((buy_price - 100.0) - (100.0 - sell_price)).abs() < 1e-9);
+
+        // Not enough liquidity on either side
+        assert_eq!(book.two_sided_quote(31), None);
+        assert_eq!(book.two_sided_quote(0), None);
+    }
+
+    #[test]
+    fn test_level_retention() {
+        let mut book = OrderBook::new().with_level_retention(2);
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 10);
+
+        // Emptied level stays published at 0 but is skipped for best prices
+        book.place_order(Side::Buy, 100, 10);
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(10))));
+        assert_eq!(book.to_json_l2(5), r#"{"bids":[],"asks":[[100,0],[101,10]],"seq":3}"#);
+
+        // Matching walks straight past it
+        let trades = book.place_order(Side::Buy, 101, 4).trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(*trades[0].price, 101);
+        book.place_order(Side::Buy, 90, 1);
+        assert_eq!(book.to_json_l2(5), r#"{"bids":[[90,1]],"asks":[[100,0],[101,6]],"seq":5}"#);
+
+        // Grace window of two operations is over
+        book.place_order(Side::Buy, 90, 1);
+        assert_eq!(book.to_json_l2(5), r#"{"bids":[[90,2]],"asks":[[101,6]],"seq":6}"#);
+        assert!(!book.asks.contains_key(&Price(100)));
+
+        // A level refilled during its grace window is kept
+        book.place_order(Side::Buy, 101, 6);
+        book.place_order(Side::Sell, 101, 3);
+        book.place_order(Side::Sell, 102, 1);
+        book.place_order(Side::Sell, 102, 1);
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(3))));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.stats(), BookStats::default());
+
+        book.place_order(Side::Buy, 99, 10);
+        book.place_order(Side::Buy, 99, 20);
+        book.place_order(Side::Buy, 98, 15);
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 25);
+        book.place_order(Side::Sell, 103, 5);
+
+        // Takes out 101 and 5 of 102
+        book.place_order(Side::Buy, 102, 15);
+
+        assert_eq!(
+            book.stats(),
+            BookStats {
+                bid_levels: 2,
+                ask_levels: 2,
+                bid_orders: 3,
+                ask_orders: 2,
+                bid_volume: 45,
+                ask_volume: 25,
+                best_bid: Some((Price(99), Quantity(30))),
+                best_ask: Some((Price(102), Quantity(20))),
+                total_trades: 2,
+                total_matched_volume: 15,
+            }
+        );
+    }
+
+    #[test]
+    fn test_hidden_liquidity_matches_first_at_midpoint() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 98, 10);
+        book.place_order(Side::Sell, 102, 10);
+        book.place_order(Side::Sell, 103, 10);
+        let hidden_id = book.place_hidden(Side::Sell, 99, 10);
+
+        // Hidden orders don't show anywhere in the lit book
+        assert_eq!(book.best_sell(), Some((Price(102), Quantity(10))));
+        assert_eq!(book.stats().ask_orders, 2);
+
+        // Incoming buy takes the hidden order at the midpoint of 100 first,
+        // then sweeps the lit ask, and rests the rest
+        let trades = book.place_order(Side::Buy, 102, 25).trades;
+        assert_eq!(fills(&trades), vec![(100, 10), (102, 10)]);
+        assert_eq!(trades[0].maker_id, hidden_id);
+        assert_eq!(book.best_buy(), Some((Price(102), Quantity(5))));
+        assert!(book.hidden_asks.is_empty());
+
+        // A hidden bid whose limit is below the midpoint of 102 doesn't take part
+        book.place_hidden(Side::Buy, 101, 10);
+        let trades = book.place_order(Side::Sell, 100, 5).trades;
+        assert_eq!(fills(&trades), vec![(102, 5)]);
+        assert_eq!(book.hidden_bids[&Price(101)].len(), 1);
+    }
+
+    #[test]
+    fn test_place_hidden_without_quantity() {
+        let listener = RecordingListener::default();
+        let mut book = OrderBook::new().with_listener(listener.clone());
+        book.place_order(Side::Buy, 98, 10);
+        book.place_order(Side::Sell, 102, 10);
+
+        // Neither rests, so nothing can trade against them
+        let empty = book.place_hidden(Side::Sell, 100, 0);
+        let negative = book.place_hidden(Side::Sell, 100, -5);
+        assert!(book.get_order(empty).is_none());
+        assert!(book.get_order(negative).is_none());
+        assert!(book.hidden_asks.is_empty());
+
+        let trades = book.place_order(Side::Buy, 102, 4).trades;
+        assert_eq!(fills(&trades), vec![(102, 4)]);
+
+        // A hidden order that does rest is reported like a lit one
+        let hidden = book.place_hidden(Side::Sell, 100, 5);
+        let rested = listener.rested_orders.borrow().last().map(|order| order.id);
+        assert_eq!(rested, Some(hidden));
+    }
+
+    #[test]
+    fn test_order_rested_listener() {
+        let listener = RecordingListener::default();
+        let mut book = OrderBook::new().with_listener(listener.clone());
+
+        // Fresh non-crossing order rests
+        book.place_order(Side::Sell, 101, 10);
+        assert_eq!(listener.rested_orders.borrow().len(), 1);
+        let rested = listener.rested_orders.borrow()[0].clone();
+        assert_eq!((rested.side, rested.price, rested.quantity), (Side::Sell, Price(101), Quantity(10)));
+        assert_eq!(rested.id, book.asks[&Price(101)][0].id);
+
+        // Fully filled on entry, nothing rests
+        book.place_order(Side::Buy, 101, 4);
+        assert_eq!(listener.rested_orders.borrow().len(), 1);
+
+        // Partially filled, the remainder rests
+        let trades = book.place_order(Side::Buy, 101, 10).trades;
+        assert_eq!(listener.rested_orders.borrow().len(), 2);
+        let rested = listener.rested_orders.borrow()[1].clone();
+        assert_eq!((rested.side, rested.price, rested.quantity), (Side::Buy, Price(101), Quantity(4)));
+        assert_eq!(rested.id, trades[0].taker_id);
+    }
+
+    #[test]
+    fn test_liquidity_half_distance() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.liquidity_half_distance(Side::Buy), None);
+
+        // Half of the 100 resting sits within the top two levels
+        book.place_order(Side::Buy, 100, 30);
+        book.place_order(Side::Buy, 99, 20);
+        book.place_order(Side::Buy, 97, 25);
+        book.place_order(Side::Buy, 95, 25);
+        assert_eq!(book.liquidity_half_distance(Side::Buy), Some(Price(1)));
+
+        // Asks with most volume further out
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 104, 10);
+        book.place_order(Side::Sell, 106, 40);
+        assert_eq!(book.liquidity_half_distance(Side::Sell), Some(Price(5)));
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut book = OrderBook::new().with_level_retention(10);
+        for _ in 0..100 {
+            book.place_order(Side::Sell, 100, 1);
+        }
+        book.place_order(Side::Sell, 101, 5);
+        book.place_order(Side::Buy, 90, 5);
+        book.place_order(Side::Buy, 100, 99);
+        book.place_order(Side::Buy, 110, 1);
+        book.place_order(Side::Sell, 102, 5);
+
+        // 100 is drained but retained, 101 untouched
+        assert!(book.asks[&Price(100)].capacity() >= 100);
+        let json = book.to_json_l2(10);
+        assert_eq!(json, r#"{"bids":[[90,5]],"asks":[[100,0],[101,5],[102,5]],"seq":105}"#);
+        let stats = book.stats();
+
+        book.shrink_to_fit();
+        assert!(!book.asks.contains_key(&Price(100)));
+        assert!(book.spare_levels.is_empty());
+        assert!(book.asks.values().all(|orders| orders.capacity() < 100));
+
+        // Nothing observable changed apart from the retained empty level
+        assert_eq!(book.stats(), stats);
+        assert_eq!(book.to_json_l2(10), r#"{"bids":[[90,5]],"asks":[[101,5],[102,5]],"seq":105}"#);
+        let trades = book.place_order(Side::Buy, 101, 5).trades;
+        assert_eq!(fills(&trades), vec![(101, 5)]);
+    }
+
+    #[test]
+    fn test_max_trades_per_order() {
+        let mut book = OrderBook::new().with_max_trades_per_order(3);
+        for _ in 0..10 {
+            book.place_order(Side::Sell, 100, 2);
+        }
+
+        let trades = book.place_order(Side::Buy, 100, 15).trades;
+        assert_eq!(fills(&trades), vec![(100, 2), (100, 2), (100, 2)]);
+
+        // Remainder rests, untouched makers keep their place
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(9))));
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(14))));
+
+        // The cap applies per order
+        let trades = book.place_order(Side::Buy, 100, 4).trades;
+        assert_eq!(trades.len(), 2);
+    }
+
+    #[test]
+    fn test_worst_fill_price() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 10);
+        book.place_order(Side::Sell, 103, 10);
+        book.place_order(Side::Sell, 105, 10);
+        book.place_order(Side::Buy, 99, 10);
+
+        assert_eq!(book.worst_fill_price(Side::Buy, 10), Some(Price(101)));
+        assert_eq!(book.worst_fill_price(Side::Buy, 25), Some(Price(103)));
+        assert_eq!(book.worst_fill_price(Side::Buy, 40), Some(Price(105)));
+        assert_eq!(book.worst_fill_price(Side::Buy, 41), None);
+        assert_eq!(book.worst_fill_price(Side::Sell, 5), Some(Price(99)));
+
+        // Sending the order at that limit fills it completely
+        let trades = book.place_order(Side::Buy, 103, 25).trades;
+        let total: i32 = trades.iter().map(|t| *t.quantity).sum();
+        assert_eq!(total, 25);
+    }
+
+    #[test]
+    fn test_max_levels_per_side_evicts_worst_level() {
+        let listener = RecordingListener::default();
+        let mut book = OrderBook::new().with_max_levels_per_side(3).with_listener(listener.clone());
+
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 99, 10);
+        book.place_order(Side::Buy, 98, 10);
+        book.place_order(Side::Buy, 98, 5);
+        let worst_ids: Vec<Uuid> = book.bids[&Price(98)].iter().map(|o| o.id).collect();
+        assert!(listener.evicted_orders.borrow().is_empty());
+
+        // A fourth, better level pushes out the worst one
+        book.place_order(Side::Buy, 101, 10);
+        assert_eq!(*listener.evicted_orders.borrow(), worst_ids);
+        let prices: Vec<Price> = book.bids.keys().copied().collect();
+        assert_eq!(prices, vec![Price(99), Price(100), Price(101)]);
+        assert_eq!(book.best_buy(), Some((Price(101), Quantity(10))));
+
+        // An order below every level is evicted straight away
+        book.place_order(Side::Buy, 90, 10);
+        assert_eq!(listener.evicted_orders.borrow().len(), 3);
+        assert_eq!(book.bids.len(), 3);
+        assert!(!book.bids.contains_key(&Price(90)));
+
+        // Asks evict from the top
+        book.place_order(Side::Sell, 105, 1);
+        book.place_order(Side::Sell, 106, 1);
+        book.place_order(Side::Sell, 107, 1);
+        book.place_order(Side::Sell, 104, 1);
+        assert_eq!(book.asks.keys().last(), Some(&Price(106)));
+    }
+
+    #[test]
+    fn test_max_levels_per_side_with_level_retention() {
+        let listener = RecordingListener::default();
+        let mut book = OrderBook::new()
+            .with_max_levels_per_side(2)
+            .with_level_retention(100)
+            .with_listener(listener.clone());
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Buy, 100, 10);
+        assert_eq!(book.asks[&Price(100)].len(), 0);
+
+        // The retained empty level at 100 doesn't count, so nothing is evicted
+        book.place_order(Side::Sell, 99, 10);
+        assert!(listener.evicted_orders.borrow().is_empty());
+        assert_eq!(book.depth(5).1, vec![(Price(99), Quantity(10)), (Price(101), Quantity(10))]);
+
+        // Over the cap, the worst level with orders goes, not the empty one
+        let worst = book.asks[&Price(101)][0].id;
+        book.place_order(Side::Sell, 98, 10);
+        assert_eq!(*listener.evicted_orders.borrow(), vec![worst]);
+        assert_eq!(book.depth(5).1, vec![(Price(98), Quantity(10)), (Price(99), Quantity(10))]);
+        assert!(book.asks.contains_key(&Price(100)));
+    }
+
+    #[test]
+    fn test_matchable_at() {
+        let mut book = OrderBook::new().with_session_state(SessionState::PreOpen);
+        book.place_order(Side::Buy, 103, 10);
+        book.place_order(Side::Buy, 102, 20);
+        book.place_order(Side::Buy, 100, 30);
+        book.place_order(Side::Sell, 99, 15);
+        book.place_order(Side::Sell, 101, 25);
+        book.place_order(Side::Sell, 104, 10);
+
+        let curve: Vec<i32> = (98..=105).map(|price| *book.matchable_at(Price(price))).collect();
+        assert_eq!(curve, vec![0, 15, 15, 30, 30, 10, 0, 0]);
+
+        // The curve peaks where the auction clears
+        let peak = (98..=105).max_by_key(|&price| *book.matchable_at(Price(price))).unwrap();
+        assert_eq!(*book.matchable_at(Price(peak)), 30);
+        let (clearing_price, trades) = book.open_from_auction();
+        assert_eq!(clearing_price, Some(Price(101)));
+        assert_eq!(trades.iter().map(|t| *t.quantity).sum::<i32>(), 30);
+    }
+
+    #[test]
+    fn test_level_discipline() {
+        let build_book = |discipline| {
+            let mut book = OrderBook::new().with_level_discipline(discipline);
+            book.place_order(Side::Sell, 100, 10);
+            book.place_order(Side::Sell, 100, 20);
+            book.place_order(Side::Sell, 100, 30);
+            book
+        };
+
+        let mut book = build_book(LevelDiscipline::Fifo);
+        let trades = book.place_order(Side::Buy, 100, 45).trades;
+        assert_eq!(fills(&trades), vec![(100, 10), (100, 20), (100, 15)]);
+        assert_eq!(*book.asks[&Price(100)][0].quantity, 15);
+
+        // Same orders fill newest first
+        let mut book = build_book(LevelDiscipline::Lifo);
+        let trades = book.place_order(Side::Buy, 100, 45).trades;
+        assert_eq!(fills(&trades), vec![(100, 30), (100, 15)]);
+        let left: Vec<i32> = book.asks[&Price(100)].iter().map(|o| *o.quantity).collect();
+        assert_eq!(left, vec![10, 5]);
+        book.assert_priority();
+    }
+
+    #[test]
+    fn test_resting_exposure() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 102, 10);
+        book.place_order(Side::Buy, 98, 20);
+        book.place_order(Side::Buy, 98, 5);
+        assert_eq!(book.resting_exposure(Side::Buy), 0);
+
+        book.set_mark_price(Price(100));
+        assert_eq!(book.mark_price(), Some(Price(100)));
+
+        // 10 * (102 - 100) + 25 * (98 - 100)
+        assert_eq!(book.resting_exposure(Side::Buy), -30);
+        assert_eq!(book.resting_exposure(Side::Sell), 0);
+
+        book.place_order(Side::Sell, 103, 4);
+        assert_eq!(book.resting_exposure(Side::Sell), 12);
+
+        // Levels that each fit in an i64 but overflow together saturate
+        let mut book = OrderBook::new();
+        let price = i64::MAX / (2 * i64::from(i32::MAX)) + 1;
+        book.place_order(Side::Sell, price, i32::MAX);
+        book.place_order(Side::Sell, price + 1, i32::MAX);
+        book.set_mark_price(Price(0));
+        assert_eq!(book.resting_exposure(Side::Sell), i64::MAX);
+    }
+
+    #[test]
+    fn test_place_order_ack() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Buy, 99, 5);
+        book.place_order(Side::Buy, 99, 5);
+
+        // Fills 10 at 100, rests 15 at the front of a new level
+        let (ack, trades) = book.place_order_ack(Side::Buy, 100, 25);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(ack.filled, Quantity(10));
+        assert_eq!(ack.resting_price, Some(Price(100)));
+        assert_eq!(ack.queue_position, Some(0));
+        assert_eq!(book.bids[&Price(100)][0].id, ack.id);
+
+        // Joins the back of an existing level
+        let (ack, _) = book.place_order_ack(Side::Buy, 99, 5);
+        assert_eq!(ack.filled, Quantity(0));
+        assert_eq!(ack.queue_position, Some(2));
+
+        // Fully filled, nothing rests
+        let (ack, _) = book.place_order_ack(Side::Sell, 100, 15);
+        assert_eq!(ack.filled, Quantity(15));
+        assert_eq!(ack.resting_price, None);
+        assert_eq!(ack.queue_position, None);
+    }
+
+    #[test]
+    fn test_trade_count_preview() {
+        let mut book = OrderBook::new();
+        for quantity in [3, 2, 4, 1] {
+            book.place_order(Side::Sell, 100, quantity);
+        }
+        book.place_order(Side::Sell, 101, 5);
+        book.place_order(Side::Sell, 101, 5);
+        book.place_order(Side::Buy, 95, 10);
+
+        assert_eq!(book.trade_count_preview(Side::Buy, 99, 10), 0);
+        assert_eq!(book.trade_count_preview(Side::Buy, 100, 4), 2);
+        assert_eq!(book.trade_count_preview(Side::Buy, 100, 50), 4);
+        assert_eq!(book.trade_count_preview(Side::Sell, 90, 5), 1);
+
+        // Prediction leaves the book alone and matches the real placement
+        let predicted = book.trade_count_preview(Side::Buy, 101, 12);
+        assert_eq!(predicted, 5);
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(10))));
+        assert_eq!(book.place_order(Side::Buy, 101, 12).trades.len(), predicted);
+    }
+
+    #[test]
+    fn test_trade_count_preview_with_iceberg() {
+        let build_book = |discipline| {
+            let mut book = OrderBook::new().with_level_discipline(discipline);
+            book.place_iceberg(Side::Sell, 100, 30, 10);
+            book.place_order(Side::Sell, 100, 5);
+            book
+        };
+
+        // Slice of 10, the plain 5, then two more slices of 10
+        let mut book = build_book(LevelDiscipline::Fifo);
+        assert_eq!(book.trade_count_preview(Side::Buy, 100, 35), 4);
+        assert_eq!(book.place_order(Side::Buy, 100, 35).trades.len(), 4);
+
+        for discipline in [LevelDiscipline::Fifo, LevelDiscipline::Lifo, LevelDiscipline::ProRata] {
+            for quantity in [3, 12, 25, 40] {
+                let mut book = build_book(discipline);
+                let predicted = book.trade_count_preview(Side::Buy, 100, quantity);
+                assert_eq!(book.place_order(Side::Buy, 100, quantity).trades.len(), predicted, "{discipline:?} {quantity}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_equal_price_crosses() {
+        // Default, a buy at exactly the best ask trades
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        let trades = book.place_order(Side::Buy, 100, 4).trades;
+        assert_eq!(fills(&trades), vec![(100, 4)]);
+
+        // Equality is not marketable, the buy rests next to the ask
+        let mut book = OrderBook::new().with_equal_price_crosses(false);
+        book.place_order(Side::Sell, 100, 10);
+        assert_eq!(book.trade_count_preview(Side::Buy, 100, 4), 0);
+        let trades = book.place_order(Side::Buy, 100, 4).trades;
+        assert!(trades.is_empty());
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(4))));
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(10))));
+
+        // Strictly better prices still trade, on both sides
+        let trades = book.place_order(Side::Buy, 101, 3).trades;
+        assert_eq!(fills(&trades), vec![(100, 3)]);
+        let trades = book.place_order(Side::Sell, 100, 1).trades;
+        assert!(trades.is_empty());
+        let trades = book.place_order(Side::Sell, 99, 1).trades;
+        assert_eq!(fills(&trades), vec![(100, 1)]);
+    }
+
+    #[test]
+    fn test_apply_all_matches_place_order_loop() {
+        let orders = [
+            (Side::Sell, 101, 10),
+            (Side::Sell, 102, 20),
+            (Side::Sell, 101, 5),
+            (Side::Buy, 99, 15),
+            (Side::Buy, 102, 25),
+            (Side::Sell, 98, 30),
+            (Side::Buy, 100, 5),
+        ];
+
+        let mut looped = OrderBook::new();
+        let looped_trades: usize = orders
+            .iter()
+            .map(|&(side, price, quantity)| looped.place_order(side, price, quantity).trades.len())
+            .sum();
+
+        let mut bulk = OrderBook::new();
+        assert_eq!(bulk.apply_all(&orders), looped_trades);
+        assert_eq!(looped_trades, 5);
+        assert_eq!(bulk.to_json_l2(10), looped.to_json_l2(10));
+        assert_eq!(bulk.stats(), looped.stats());
+    }
+
+    #[test]
+    fn test_place_order_checked() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 10);
+
+        let trades = book.place_order_checked(Side::Buy, 101, 15).unwrap();
+        assert_eq!(fills(&trades), vec![(100, 10), (101, 5)]);
+
+        // Corrupt the book with an empty resting order at the touch
+        let empty_order = Order::new(Uuid::new_v4(), Side::Sell, Price(99), Quantity(0));
+        book.asks.entry(Price(99)).or_default().push_back(empty_order);
+        assert_eq!(
+            book.place_order_checked(Side::Buy, 101, 2),
+            Err(MatchAnomaly::ZeroQuantity { trade: 0 })
+        );
+
+        // The corrupt order is gone and checks pass again
+        assert!(book.place_order_checked(Side::Buy, 101, 1).is_ok());
+    }
+
+    #[test]
+    fn test_min_quote_size() {
+        let mut book = OrderBook::new().with_min_quote_size(Side::Buy, 10).with_min_quote_size(Side::Sell, 10);
+        book.place_order(Side::Sell, 101, 20);
+
+        // Too small to rest passively
+        let (ack, trades) = book.place_order_ack(Side::Buy, 99, 5);
+        assert!(trades.is_empty());
+        assert_eq!(ack.resting_price, None);
+        assert_eq!(book.best_buy(), None);
+
+        // Same size trades fine as an aggressive order
+        let trades = book.place_order(Side::Buy, 101, 5).trades;
+        assert_eq!(fills(&trades), vec![(101, 5)]);
+
+        // Remainders below the minimum are cancelled too, larger ones rest
+        book.place_order(Side::Buy, 101, 20);
+        assert_eq!(book.best_buy(), None);
+        book.place_order(Side::Sell, 101, 20);
+        book.place_order(Side::Buy, 101, 30);
+        assert_eq!(book.best_buy(), Some((Price(101), Quantity(10))));
+    }
+
+    #[test]
+    fn test_walls() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 15);
+        book.place_order(Side::Sell, 103, 200);
+        book.place_order(Side::Sell, 103, 50);
+        book.place_order(Side::Sell, 104, 20);
+
+        assert_eq!(book.walls(Side::Sell, Quantity(100)), vec![(Price(103), Quantity(250))]);
+        assert_eq!(
+            book.walls(Side::Sell, Quantity(15)),
+            vec![(Price(102), Quantity(15)), (Price(103), Quantity(250)), (Price(104), Quantity(20))]
+        );
+        assert!(book.walls(Side::Buy, Quantity(1)).is_empty());
+    }
+
+    #[test]
+    fn test_reconstruct_positions() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 101, 10);
+        let seller = book.asks[&Price(101)][0].id;
+        book.place_order(Side::Buy, 99, 20);
+        let bidder = book.bids[&Price(99)][0].id;
+
+        let mut trades = book.place_order(Side::Buy, 101, 4).trades;
+        let buyer = trades[0].taker_id;
+        assert_eq!(trades[0].taker_side, Side::Buy);
+        let sell_trades = book.place_order(Side::Sell, 99, 15).trades;
+        let aggressive_seller = sell_trades[0].taker_id;
+        assert_eq!(sell_trades[0].taker_side, Side::Sell);
+        trades.extend(sell_trades);
+
+        let positions = OrderBook::reconstruct_positions(&trades);
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[&buyer], 4);
+        assert_eq!(positions[&seller], -4);
+        assert_eq!(positions[&bidder], 15);
+        assert_eq!(positions[&aggressive_seller], -15);
+        assert_eq!(positions.values().sum::<i64>(), 0);
+    }
+
+    #[test]
+    fn test_from_levels_strict_valid() {
+        let bids = [(Price(99), Quantity(10)), (Price(98), Quantity(20))];
+        let asks = [(Price(101), Quantity(5)), (Price(102), Quantity(15))];
+
+        let book = OrderBook::from_levels_strict(&bids, &asks).unwrap();
+        assert_eq!(book.best_buy(), Some((Price(99), Quantity(10))));
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(5))));
+        assert_eq!(book.to_json_l2(5), r#"{"bids":[[99,10],[98,20]],"asks":[[101,5],[102,15]],"seq":4}"#);
+    }
+
+    #[test]
+    fn test_from_levels_strict_crossed() {
+        let bids = [(Price(101), Quantity(10)), (Price(98), Quantity(20))];
+        let asks = [(Price(101), Quantity(5)), (Price(102), Quantity(15))];
+        assert_eq!(OrderBook::from_levels_strict(&bids, &asks).unwrap_err(), ImportError::Crossed);
+    }
+
+    #[test]
+    fn test_from_levels_strict_unsorted() {
+        let asks = [(Price(101), Quantity(5)), (Price(102), Quantity(15))];
+        let ascending_bids = [(Price(98), Quantity(20)), (Price(99), Quantity(10))];
+        assert_eq!(OrderBook::from_levels_strict(&ascending_bids, &asks).unwrap_err(), ImportError::Unsorted);
+
+        let bids = [(Price(99), Quantity(10))];
+        let duplicate_asks = [(Price(101), Quantity(5)), (Price(101), Quantity(15))];
+        assert_eq!(OrderBook::from_levels_strict(&bids, &duplicate_asks).unwrap_err(), ImportError::Unsorted);
+
+        // The lenient import takes the same data as is
+        let book = OrderBook::from_levels(&bids, &duplicate_asks);
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(20))));
+    }
+
+    #[test]
+    fn test_estimated_queue_clear() {
+        let mut book = OrderBook::new().with_trade_history(16);
+        assert_eq!(book.expected_fill_volume_rate(5), None);
+
+        // Steady history of 5-lot fills
+        book.place_order(Side::Sell, 105, 100);
+        for _ in 0..4 {
+            book.place_order(Side::Buy, 105, 5);
+        }
+        assert_eq!(book.expected_fill_volume_rate(3), Some(5.0));
+
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 100, 20);
+        book.place_order(Side::Buy, 100, 15);
+        let id = book.bids[&Price(100)][2].id;
+        assert_eq!(book.volume_ahead(id), Some(Quantity(30)));
+        assert_eq!(book.estimated_queue_clear(id), Some(6.0));
+
+        let front = book.bids[&Price(100)][0].id;
+        assert_eq!(book.estimated_queue_clear(front), Some(0.0));
+        assert_eq!(book.estimated_queue_clear(Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn test_cancel_front() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 100, 20);
+        book.place_order(Side::Buy, 100, 30);
+        let ids: Vec<Uuid> = book.bids[&Price(100)].iter().map(|o| o.id).collect();
+
+        assert_eq!(book.cancel_front(Side::Buy, 100), Some(ids[0]));
+        assert_eq!(book.cancel_front(Side::Buy, 100), Some(ids[1]));
+        assert_eq!(book.bids[&Price(100)].len(), 1);
+        assert_eq!(book.bids[&Price(100)][0].id, ids[2]);
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(30))));
+
+        // Last order out removes the level
+        assert_eq!(book.cancel_front(Side::Buy, 100), Some(ids[2]));
+        assert!(book.bids.is_empty());
+        assert_eq!(book.cancel_front(Side::Buy, 100), None);
+        assert_eq!(book.cancel_front(Side::Sell, 100), None);
+    }
+
+    #[test]
+    fn test_tape() {
+        let mut book = OrderBook::new().with_trade_history(16);
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 20);
+        book.place_order(Side::Buy, 99, 5);
+
+        book.place_order(Side::Buy, 101, 15);
+        book.place_order(Side::Sell, 99, 5);
+
+        assert_eq!(
+            book.tape(),
+            vec![
+                (4, Price(100), Quantity(10), Side::Buy, Quantity(10)),
+                (4, Price(101), Quantity(5), Side::Buy, Quantity(15)),
+                (5, Price(99), Quantity(5), Side::Sell, Quantity(20)),
+            ]
+        );
+        assert!(OrderBook::new().tape().is_empty());
+
+        // The running total saturates instead of overflowing
+        let mut book = OrderBook::new().with_trade_history(16);
+        for _ in 0..3 {
+            book.place_order(Side::Sell, 100, i32::MAX);
+            book.place_order(Side::Buy, 100, i32::MAX);
+        }
+        let cumulative: Vec<Quantity> = book.tape().iter().map(|&(.., cumulative)| cumulative).collect();
+        assert_eq!(cumulative, vec![Quantity(i32::MAX); 3]);
+    }
+
+    #[test]
+    fn test_cursor() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 99, 10);
+        book.place_order(Side::Buy, 100, 20);
+        book.place_order(Side::Buy, 100, 5);
+        book.place_order(Side::Buy, 98, 30);
+        book.place_order(Side::Sell, 101, 40);
+
+        let mut cursor = book.cursor(Side::Buy);
+        assert_eq!(cursor.next_level(), Some((Price(100), Quantity(25))));
+        assert_eq!(cursor.next_level(), Some((Price(99), Quantity(10))));
+
+        // Stopping early leaves the book free to change again
+        book.place_order(Side::Sell, 102, 40);
+        let mut cursor = book.cursor(Side::Sell);
+        assert_eq!(cursor.next_level(), Some((Price(101), Quantity(40))));
+        assert_eq!(cursor.next_level(), Some((Price(102), Quantity(40))));
+        assert_eq!(cursor.next_level(), None);
+    }
+
+    #[test]
+    fn test_quote_adjustments() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 101, 10);
+        book.place_order(Side::Buy, 100, 5);
+        book.place_order(Side::Buy, 100, 5);
+        book.place_order(Side::Sell, 102, 6);
+        book.place_order(Side::Sell, 102, 6);
+        book.place_order(Side::Sell, 103, 10);
+
+        let target_bid = (Price(100), Quantity(20));
+        let target_ask = (Price(102), Quantity(10));
+        let commands = book.quote_adjustments(target_bid, target_ask);
+        assert_eq!(
+            commands,
+            vec![
+                Command::CancelFront { side: Side::Buy, price: 101 },
+                Command::CancelFront { side: Side::Sell, price: 102 },
+                Command::Place { side: Side::Buy, price: 100, quantity: 10 },
+                Command::Place { side: Side::Sell, price: 102, quantity: 4 },
+            ]
+        );
+
+        for command in &commands {
+            assert!(book.apply(command).is_empty());
+        }
+        assert_eq!(book.best_buy(), Some(target_bid));
+        assert_eq!(book.best_sell(), Some(target_ask));
+        assert!(book.quote_adjustments(target_bid, target_ask).is_empty());
+    }
+
+    #[test]
+    fn test_place_result() {
+        let mut book = OrderBook::new().with_min_quote_size(Side::Buy, 5);
+        book.place_order(Side::Sell, 100, 10);
+
+        // Partially filled, the remainder rests under the returned id
+        let result = book.place_order(Side::Buy, 100, 25);
+        assert_eq!(fills(&result.trades), vec![(100, 10)]);
+        assert_eq!(result.remaining, Quantity(15));
+        assert_eq!(book.bids[&Price(100)][0].id, result.order_id);
+
+        // Fully filled
+        let result = book.place_order(Side::Sell, 100, 15);
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.remaining, Quantity(0));
+
+        assert_eq!(result.discarded, Quantity(0));
+
+        // Remainder below the minimum quote size is dropped, not rested, and
+        // reported as such rather than looking like a full fill
+        book.place_order(Side::Sell, 101, 8);
+        let result = book.place_order(Side::Buy, 101, 10);
+        assert_eq!(result.remaining, Quantity(0));
+        assert_eq!(result.discarded, Quantity(2));
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let mut book = OrderBook::new();
+        let first = book.place_order(Side::Sell, 101, 10).order_id;
+        let second = book.place_order(Side::Sell, 101, 20).order_id;
+        let third = book.place_order(Side::Sell, 101, 30).order_id;
+        let lone = book.place_order(Side::Sell, 102, 5).order_id;
+
+        // Cancelling from the middle keeps the others in order
+        let cancelled = book.cancel_order(second).unwrap();
+        assert_eq!((cancelled.id, cancelled.quantity), (second, Quantity(20)));
+        let ids: Vec<Uuid> = book.asks[&Price(101)].iter().map(|order| order.id).collect();
+        assert_eq!(ids, vec![first, third]);
+        assert_eq!(book.cancel_order(second), Err(OrderError::OrderNotFound));
+
+        // Last order out removes the level
+        book.cancel_order(lone).unwrap();
+        assert!(!book.asks.contains_key(&Price(102)));
+
+        // Filled orders leave the index, partially filled ones stay cancellable
+        book.place_order(Side::Buy, 101, 15);
+        assert_eq!(book.cancel_order(first), Err(OrderError::OrderNotFound));
+        assert_eq!(book.cancel_order(third).unwrap().quantity, Quantity(25));
+        assert!(book.asks.is_empty());
+        assert!(book.order_index.is_empty());
+
+        // Hidden orders can be cancelled too
+        let hidden = book.place_hidden(Side::Buy, 100, 10);
+        assert_eq!(book.cancel_order(hidden).unwrap().id, hidden);
+        assert!(book.hidden_bids.is_empty());
+        assert_eq!(book.cancel_order(Uuid::new_v4()), Err(OrderError::OrderNotFound));
+    }
+
+    #[test]
+    fn test_amend_quantity() {
+        let mut book = OrderBook::new();
+        let first = book.place_order(Side::Buy, 100, 10).order_id;
+        let second = book.place_order(Side::Buy, 100, 20).order_id;
+        let third = book.place_order(Side::Buy, 100, 30).order_id;
+        let order_ids = |book: &OrderBook| -> Vec<Uuid> { book.bids[&Price(100)].iter().map(|order| order.id).collect() };
+
+        // A reduction keeps the queue position
+        book.amend_quantity(first, 4).unwrap();
+        assert_eq!(order_ids(&book), vec![first, second, third]);
+        assert_eq!(book.bids[&Price(100)][0].quantity, Quantity(4));
+
+        // An increase goes to the back
+        book.amend_quantity(second, 25).unwrap();
+        assert_eq!(order_ids(&book), vec![first, third, second]);
+        assert_eq!(book.bids[&Price(100)][2].quantity, Quantity(25));
+        book.assert_priority();
+
+        assert_eq!(book.amend_quantity(first, -1), Err(OrderError::NonPositiveQuantity));
+        assert_eq!(book.amend_quantity(Uuid::new_v4(), 5), Err(OrderError::OrderNotFound));
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(59))));
+    }
+
+    #[test]
+    fn test_amend_hidden_order() {
+        let mut book = OrderBook::new();
+        let first = book.place_hidden(Side::Sell, 100, 10);
+        let second = book.place_hidden(Side::Sell, 100, 10);
+
+        book.amend_quantity(first, 4).unwrap();
+        assert_eq!(book.get_order(first).map(|order| order.quantity), Some(Quantity(4)));
+
+        // An increase loses priority in the hidden level too
+        book.amend_quantity(first, 12).unwrap();
+        let ids: Vec<Uuid> = book.hidden_asks[&Price(100)].iter().map(|order| order.id).collect();
+        assert_eq!(ids, vec![second, first]);
+
+        book.amend_quantity(second, 0).unwrap();
+        assert_eq!(book.get_order(second), None);
+        assert_eq!(book.get_order(first).map(|order| order.quantity), Some(Quantity(12)));
+        assert_eq!(book.best_sell(), None);
+    }
+
+    #[test]
+    fn test_place_market_order() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 150, 10);
+        book.place_order(Side::Sell, 10_000, 10);
+
+        let trades = book.place_market_order(Side::Buy, 25);
+        assert_eq!(fills(&trades), vec![(100, 10), (150, 10), (10_000, 5)]);
+        assert_eq!(book.best_sell(), Some((Price(10_000), Quantity(5))));
+
+        // Runs the side dry and drops the rest instead of resting it
+        let trades = book.place_market_order(Side::Buy, 20);
+        assert_eq!(fills(&trades), vec![(10_000, 5)]);
+        assert!(book.asks.is_empty());
+        assert!(book.bids.is_empty());
+
+        // Empty book, nothing happens
+        assert!(book.place_market_order(Side::Sell, 10).is_empty());
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+    }
+
+    #[test]
+    fn test_place_ioc_full_fill() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 10);
+
+        let trades = book.place_ioc(Side::Buy, 101, 15);
+        assert_eq!(fills(&trades), vec![(100, 10), (101, 5)]);
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(5))));
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn test_place_ioc_discards_remainder() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 102, 10);
+
+        let trades = book.place_ioc(Side::Buy, 101, 25);
+        assert_eq!(fills(&trades), vec![(100, 10)]);
+        assert!(book.bids.is_empty());
+        assert_eq!(book.best_sell(), Some((Price(102), Quantity(10))));
+    }
+
+    #[test]
+    fn test_place_ioc_no_match() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 99, 10);
+
+        assert!(book.place_ioc(Side::Sell, 100, 10).is_empty());
+        assert!(book.asks.is_empty());
+        assert_eq!(book.best_buy(), Some((Price(99), Quantity(10))));
+    }
+
+    #[test]
+    fn test_place_fok() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 103, 10);
+
+        // Only 20 available up to 101, the book is left as it was
+        assert_eq!(book.place_fok(Side::Buy, 101, 25), None);
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(10))));
+        assert!(book.bids.is_empty());
+
+        let trades = book.place_fok(Side::Buy, 101, 15).unwrap();
+        assert_eq!(fills(&trades), vec![(100, 10), (101, 5)]);
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(5))));
+
+        // Enough quantity, but the trade cap would cut it short
+        let mut book = OrderBook::new().with_max_trades_per_order(1);
+        book.place_order(Side::Sell, 100, 5);
+        book.place_order(Side::Sell, 100, 5);
+        assert_eq!(book.place_fok(Side::Buy, 100, 10), None);
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(10))));
+
+        // An iceberg's reserve counts towards what can fill
+        let mut book = OrderBook::new();
+        book.place_iceberg(Side::Sell, 100, 30, 10);
+        let trades = book.place_fok(Side::Buy, 100, 25).unwrap();
+        assert_eq!(fills(&trades), vec![(100, 10), (100, 10), (100, 5)]);
+        assert_eq!(book.place_fok(Side::Buy, 100, 6), None);
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(5))));
+    }
+
+    #[test]
+    fn test_place_post_only() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Buy, 99, 10);
+
+        assert_eq!(book.place_post_only(Side::Buy, 101, 5), Err(PostOnlyRejected::WouldCross { best_opposite: Price(101) }));
+        assert_eq!(book.place_post_only(Side::Sell, 98, 5), Err(PostOnlyRejected::WouldCross { best_opposite: Price(99) }));
+        assert_eq!(book.place_post_only(Side::Buy, 100, 0), Err(PostOnlyRejected::NonPositiveQuantity));
+        assert_eq!(book.place_post_only(Side::Sell, 102, -5), Err(PostOnlyRejected::NonPositiveQuantity));
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(10))));
+        assert_eq!(book.best_buy(), Some((Price(99), Quantity(10))));
+
+        let id = book.place_post_only(Side::Buy, 100, 5).unwrap();
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(5))));
+        assert_eq!(book.bids[&Price(100)][0].id, id);
+
+        // Would trade a hidden order at the midpoint, so it only rests
+        book.place_hidden(Side::Sell, 100, 10);
+        book.place_post_only(Side::Buy, 100, 5).unwrap();
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(10))));
+        assert_eq!(book.hidden_asks[&Price(100)][0].quantity, Quantity(10));
+    }
+
+    #[test]
+    fn test_self_trade_cancel_resting() {
+        let mut book = OrderBook::new().with_self_trade_policy(SelfTradePolicy::CancelResting);
+        let own = book.place_order_as(1, Side::Sell, 100, 10).order_id;
+        let other = book.place_order_as(2, Side::Sell, 100, 5).order_id;
+
+        let result = book.place_order_as(1, Side::Buy, 100, 8);
+        assert_eq!(fills(&result.trades), vec![(100, 5)]);
+        assert_eq!(result.trades[0].maker_id, other);
+        assert_eq!(result.remaining, Quantity(3));
+        assert!(book.asks.is_empty());
+        assert_eq!(book.cancel_order(own), Err(OrderError::OrderNotFound));
+    }
+
+    #[test]
+    fn test_self_trade_cancel_incoming() {
+        let mut book = OrderBook::new().with_self_trade_policy(SelfTradePolicy::CancelIncoming);
+        book.place_order_as(2, Side::Sell, 100, 5);
+        book.place_order_as(1, Side::Sell, 100, 10);
+
+        // Trades with the other owner, then stops at its own order
+        let result = book.place_order_as(1, Side::Buy, 100, 20);
+        assert_eq!(fills(&result.trades), vec![(100, 5)]);
+        assert_eq!(result.remaining, Quantity(0));
+        assert!(book.bids.is_empty());
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(10))));
+
+        // Orders without an owner are never caught
+        assert_eq!(fills(&book.place_order(Side::Buy, 100, 4).trades), vec![(100, 4)]);
+    }
+
+    #[test]
+    fn test_self_trade_allowed_by_default() {
+        let mut book = OrderBook::new();
+        book.place_order_as(1, Side::Sell, 100, 10);
+        let result = book.place_order_as(1, Side::Buy, 100, 10);
+        assert_eq!(fills(&result.trades), vec![(100, 10)]);
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 98, 10);
+        book.place_order(Side::Buy, 100, 5);
+        book.place_order(Side::Buy, 100, 7);
+        book.place_order(Side::Buy, 99, 20);
+        book.place_order(Side::Sell, 102, 15);
+
+        let (bids, asks) = book.depth(2);
+        assert_eq!(bids, vec![(Price(100), Quantity(12)), (Price(99), Quantity(20))]);
+        assert_eq!(asks, vec![(Price(102), Quantity(15))]);
+
+        let (bids, asks) = book.depth(0);
+        assert!(bids.is_empty() && asks.is_empty());
+    }
+
+    #[test]
+    fn test_spread_and_mid_price() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.spread(), None);
+        assert_eq!(book.mid_price(), None);
+
+        book.place_order(Side::Buy, 100, 10);
+        assert_eq!(book.spread(), None);
+        assert_eq!(book.mid_price(), None);
+
+        book.place_order(Side::Sell, 103, 10);
+        assert_eq!(book.spread(), Some(Price(3)));
+        assert_eq!(book.mid_price(), Some(101.5));
+
+        // Extreme prices don't overflow
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, i64::MAX - 1, 1);
+        book.place_order(Side::Sell, i64::MAX, 1);
+        assert_eq!(book.spread(), Some(Price(1)));
+        assert_eq!(book.mid_price(), Some(i64::MAX as f64));
+    }
+
+    #[test]
+    fn test_hidden_midpoint_at_extreme_prices() {
+        // Every placement works out the midpoint for hidden matching
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 2_000_000_000, 1);
+        book.place_order(Side::Sell, 2_000_000_001, 1);
+        assert!(book.place_order(Side::Buy, 1_999_999_999, 1).trades.is_empty());
+
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, i64::MAX - 3, 1);
+        book.place_order(Side::Sell, i64::MAX - 1, 1);
+        book.place_hidden(Side::Sell, i64::MAX - 2, 5);
+        let trades = book.place_order(Side::Buy, i64::MAX - 2, 2).trades;
+        assert_eq!(fills(&trades), vec![(i64::MAX - 2, 2)]);
+    }
+
+    #[test]
+    fn test_try_place_order() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Buy, 99, 10);
+        let sequence = book.sequence();
+
+        assert_eq!(book.try_place_order(Side::Buy, 100, 0), Err(OrderError::NonPositiveQuantity));
+        assert_eq!(book.try_place_order(Side::Buy, -5, 0), Err(OrderError::NonPositiveQuantity));
+        assert_eq!(book.try_place_order(Side::Sell, 0, 10), Err(OrderError::NonPositivePrice));
+        assert_eq!(book.try_place_order(Side::Sell, -5, 10), Err(OrderError::NonPositivePrice));
+
+        // Nothing about the book changed
+        assert_eq!(book.sequence(), sequence);
+        assert_eq!(book.depth(10), (vec![(Price(99), Quantity(10))], vec![(Price(100), Quantity(10))]));
+
+        let result = book.try_place_order(Side::Buy, 100, 4).unwrap();
+        assert_eq!(fills(&result.trades), vec![(100, 4)]);
+    }
+
+    #[test]
+    fn test_aggregate_quantity_saturates() {
+        let mut book = OrderBook::new();
+        for _ in 0..5 {
+            book.place_order(Side::Buy, 100, 1_000_000_000);
+        }
+
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(i32::MAX))));
+        assert_eq!(level_volume(&book.bids[&Price(100)]), 5_000_000_000);
+        assert_eq!(book.stats().bid_volume, 5_000_000_000);
+    }
+
+    #[test]
+    fn test_total_volume() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.total_volume(Side::Buy), Quantity(0));
+
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 100, 5);
+        book.place_order(Side::Buy, 98, 20);
+        book.place_order(Side::Sell, 101, 7);
+
+        assert_eq!(book.total_volume(Side::Buy), Quantity(35));
+        assert_eq!(book.total_volume(Side::Sell), Quantity(7));
+    }
+
+    #[test]
+    fn test_order_count() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.total_order_count(), 0);
+
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 100, 5);
+        book.place_order(Side::Buy, 98, 20);
+        book.place_order(Side::Sell, 101, 7);
+        assert_eq!(book.order_count(Side::Buy), 3);
+        assert_eq!(book.order_count(Side::Sell), 1);
+        assert_eq!(book.total_order_count(), 4);
+
+        book.place_order(Side::Sell, 100, 12);
+        assert_eq!(book.order_count(Side::Buy), 2);
+        assert_eq!(book.total_order_count(), 3);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 100, 20);
+        book.place_order(Side::Buy, 99, 5);
+        book.place_order(Side::Sell, 102, 8);
+        book.place_order(Side::Sell, 102, 4);
+        book.place_order(Side::Sell, 103, 6);
+        book.place_hidden(Side::Sell, 105, 3);
+        let stop = book.place_stop(Side::Buy, 103, Some(104), 5).unwrap();
+
+        let snapshot = book.snapshot();
+        assert_eq!(snapshot.stops.iter().map(|stop| stop.id).collect::<Vec<_>>(), vec![stop]);
+        let mut restored = OrderBook::from_snapshot(snapshot.clone());
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.best_buy(), book.best_buy());
+        assert_eq!(restored.best_sell(), book.best_sell());
+        assert_eq!(restored.sequence(), book.sequence());
+
+        // The same order flow trades against the same makers in the same
+        // order, and reaching 103 triggers the restored stop as well
+        let original = book.place_order(Side::Buy, 103, 15).trades;
+        let replayed = restored.place_order(Side::Buy, 103, 15).trades;
+        let makers = |trades: &[Trade]| -> Vec<(Uuid, i32)> { trades.iter().map(|trade| (trade.maker_id, *trade.quantity)).collect() };
+        assert_eq!(makers(&replayed), makers(&original));
+        assert_eq!(makers(&original)[0].0, snapshot.asks[0].id);
+        assert_eq!(replayed.last().map(|trade| trade.taker_id), Some(stop));
+
+        // Restored orders can still be cancelled by id
+        assert!(restored.cancel_order(snapshot.bids[1].id).is_ok());
+    }
+
+    #[test]
+    fn test_event_handler() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut book = OrderBook::new();
+        let sink = Rc::clone(&events);
+        book.set_event_handler(Box::new(move |event| sink.borrow_mut().push(event)));
+
+        let maker = book.place_order(Side::Sell, 100, 10).order_id;
+        let taker = book.place_order(Side::Buy, 100, 15);
+        book.amend_quantity(taker.order_id, 3).unwrap();
+        book.cancel_order(taker.order_id).unwrap();
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 5);
+        assert!(matches!(&events[0], BookEvent::OrderAdded(order) if (order.id, order.quantity) == (maker, Quantity(10))));
+        assert_eq!(events[1], BookEvent::TradeExecuted(taker.trades[0].clone()));
+        assert!(matches!(&events[2], BookEvent::OrderAdded(order) if (order.id, order.quantity) == (taker.order_id, Quantity(5))));
+        assert_eq!(events[3], BookEvent::OrderAmended { id: taker.order_id, old_qty: Quantity(5), new_qty: Quantity(3) });
+        assert_eq!(events[4], BookEvent::OrderCancelled(taker.order_id));
+    }
+
+    #[test]
+    fn test_tick_size() {
+        let mut book = OrderBook::new().with_tick_size(5);
+
+        assert_eq!(book.try_place_order(Side::Buy, 103, 10), Err(OrderError::InvalidTick));
+        assert!(book.bids.is_empty());
+
+        book.try_place_order(Side::Buy, 105, 10).unwrap();
+        assert_eq!(book.best_buy(), Some((Price(105), Quantity(10))));
+    }
+
+    #[test]
+    fn test_lot_size() {
+        let mut book = OrderBook::new().with_lot_size(100).with_min_quantity(200);
+
+        assert_eq!(book.try_place_order(Side::Buy, 100, 250), Err(OrderError::InvalidLotSize));
+        assert_eq!(book.try_place_order(Side::Buy, 100, 100), Err(OrderError::InvalidLotSize));
+        assert!(book.bids.is_empty());
+
+        book.try_place_order(Side::Buy, 100, 300).unwrap();
+        let result = book.try_place_order(Side::Sell, 100, 500).unwrap();
+        assert_eq!(fills(&result.trades), vec![(100, 300)]);
+        assert_eq!(result.remaining, Quantity(200));
+    }
+
+    #[test]
+    fn test_place_iceberg() {
+        let mut book = OrderBook::new();
+        let iceberg = book.place_iceberg(Side::Sell, 100, 30, 10);
+        assert_eq!(iceberg.remaining, Quantity(30));
+        let other = book.place_order(Side::Sell, 100, 5).order_id;
+
+        // Only the visible slice is reported
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(15))));
+        assert_eq!(book.total_volume(Side::Sell), Quantity(15));
+        assert_eq!(book.depth(1).1, vec![(Price(100), Quantity(15))]);
+
+        // Sweeping the slice releases the next one behind the other order
+        let trades = book.place_order(Side::Buy, 100, 10).trades;
+        assert_eq!(trades[0].maker_id, iceberg.order_id);
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(15))));
+        let queue: Vec<Uuid> = book.asks[&Price(100)].iter().map(|order| order.id).collect();
+        assert_eq!(queue, vec![other, iceberg.order_id]);
+        book.assert_priority();
+
+        let trades = book.place_order(Side::Buy, 100, 12).trades;
+        assert_eq!(fills(&trades), vec![(100, 5), (100, 7)]);
+        assert_eq!(trades[0].maker_id, other);
+
+        // The last slice is smaller than the display size
+        book.place_order(Side::Buy, 100, 3);
+        assert_eq!(book.best_sell(), Some((Price(100), Quantity(10))));
+        book.place_order(Side::Buy, 100, 10);
+        assert!(book.asks.is_empty());
+        assert_eq!(book.cancel_order(iceberg.order_id), Err(OrderError::OrderNotFound));
+    }
+
+    #[test]
+    fn test_place_iceberg_lifo() {
+        let mut book = OrderBook::new().with_level_discipline(LevelDiscipline::Lifo);
+        let other = book.place_order(Side::Sell, 100, 5).order_id;
+        let iceberg = book.place_iceberg(Side::Sell, 100, 30, 10).order_id;
+
+        // The newest order fills first, and its next slice goes to the end
+        // that fills last, the front
+        let trades = book.place_order(Side::Buy, 100, 12).trades;
+        assert_eq!(fills(&trades), vec![(100, 10), (100, 2)]);
+        assert_eq!((trades[0].maker_id, trades[1].maker_id), (iceberg, other));
+        let queue: Vec<Uuid> = book.asks[&Price(100)].iter().map(|order| order.id).collect();
+        assert_eq!(queue, vec![iceberg, other]);
+        book.assert_priority();
+
+        // The preview follows the same order
+        assert_eq!(book.trade_count_preview(Side::Buy, 100, 5), 2);
+        let trades = book.place_order(Side::Buy, 100, 5).trades;
+        assert_eq!(fills(&trades), vec![(100, 3), (100, 2)]);
+        assert_eq!((trades[0].maker_id, trades[1].maker_id), (other, iceberg));
+    }
+
+    #[test]
+    fn test_stop_orders() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 5);
+        book.place_order(Side::Sell, 101, 20);
+        let stop_market = book.place_stop(Side::Buy, 101, None, 5).unwrap();
+        let stop_limit = book.place_stop(Side::Sell, 95, Some(96), 10).unwrap();
+        let far_stop = book.place_stop(Side::Buy, 110, None, 5).unwrap();
+
+        // Not triggered yet
+        let trades = book.place_order(Side::Buy, 100, 5).trades;
+        assert_eq!(fills(&trades), vec![(100, 5)]);
+        assert_eq!(book.stop_orders.len(), 3);
+
+        // The trade at 101 triggers the buy stop, which fills after it
+        let trades = book.place_order(Side::Buy, 101, 5).trades;
+        assert_eq!(fills(&trades), vec![(101, 5), (101, 5)]);
+        assert_eq!(trades[1].taker_id, stop_market);
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(10))));
+
+        // A stop-limit rests at its limit once triggered
+        book.place_order(Side::Buy, 95, 3);
+        let trades = book.place_order(Side::Sell, 95, 3).trades;
+        assert_eq!(fills(&trades), vec![(95, 3)]);
+        assert_eq!(book.best_sell(), Some((Price(96), Quantity(10))));
+        assert_eq!(book.asks[&Price(96)][0].id, stop_limit);
+
+        assert_eq!(book.stop_orders.len(), 1);
+        assert_eq!(book.stop_orders[0].id, far_stop);
+
+        // Pending stops can be cancelled, and need a quantity
+        assert_eq!(book.cancel_stop(far_stop).map(|stop| stop.id), Ok(far_stop));
+        assert_eq!(book.cancel_stop(far_stop), Err(OrderError::OrderNotFound));
+        assert!(book.stop_orders.is_empty());
+        assert_eq!(book.place_stop(Side::Buy, 110, None, 0), Err(OrderError::NonPositiveQuantity));
+        assert!(book.stop_orders.is_empty());
+    }
+
+    #[test]
+    fn test_open_from_auction_triggers_stops() {
+        let mut book = OrderBook::new().with_session_state(SessionState::PreOpen);
+        book.place_order(Side::Buy, 101, 10);
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 102, 10);
+        let stop = book.place_stop(Side::Buy, 100, None, 4).unwrap();
+        let far_stop = book.place_stop(Side::Buy, 105, None, 4).unwrap();
+
+        // The auction clears at 100, which triggers the first stop into the
+        // open book, after the auction trade
+        let (clearing_price, trades) = book.open_from_auction();
+        assert_eq!(clearing_price, Some(Price(100)));
+        assert_eq!(fills(&trades), vec![(100, 10), (102, 4)]);
+        assert_eq!(trades[1].taker_id, stop);
+        assert_eq!(book.stop_orders.iter().map(|stop| stop.id).collect::<Vec<_>>(), vec![far_stop]);
+    }
+
+    #[test]
+    fn test_quote() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 104, 30);
+        book.place_order(Side::Buy, 98, 10);
+
+        // 10 @ 100 + 10 @ 104
+        assert_eq!(book.quote(Side::Buy, 20), Some((Quantity(20), Price(102))));
+        // 10 @ 100 + 30 @ 104 = 103, with 10 left unfilled
+        assert_eq!(book.quote(Side::Buy, 50), Some((Quantity(40), Price(103))));
+        assert_eq!(book.quote(Side::Sell, 5), Some((Quantity(5), Price(98))));
+        assert_eq!(book.quote(Side::Sell, 0), None);
+
+        // Read-only
+        assert_eq!(book.total_volume(Side::Sell), Quantity(40));
+        assert_eq!(OrderBook::new().quote(Side::Buy, 10), None);
+    }
+
+    #[test]
+    fn test_place_batch() {
+        let mut book = OrderBook::new();
+        let results = book.place_batch(&[(Side::Sell, 100, 10), (Side::Buy, 100, 4), (Side::Buy, 99, 5)]);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].remaining, Quantity(10));
+        // Later orders see the earlier ones
+        assert_eq!(fills(&results[1].trades), vec![(100, 4)]);
+        assert_eq!(book.depth(1), (vec![(Price(99), Quantity(5))], vec![(Price(100), Quantity(6))]));
+    }
+
+    #[test]
+    fn test_place_batch_atomic() {
+        let mut book = OrderBook::new();
+        let rejected = book.place_batch_atomic(&[(Side::Sell, 100, 10), (Side::Buy, 100, 4), (Side::Buy, 0, 5), (Side::Buy, 99, 0)]);
+        assert_eq!(rejected.unwrap_err(), (2, OrderError::NonPositivePrice));
+        assert_eq!(book.total_order_count(), 0);
+        assert_eq!(book.sequence(), 0);
+
+        let results = book.place_batch_atomic(&[(Side::Sell, 100, 10), (Side::Buy, 100, 4)]).unwrap();
+        assert_eq!(fills(&results[1].trades), vec![(100, 4)]);
+    }
+
+    #[test]
+    fn test_trade_timestamps() {
+        let clock = ManualClock::new(1_000);
+        let mut book = OrderBook::new().with_clock(clock.clone());
+        book.place_order(Side::Sell, 100, 10);
+        clock.advance(50);
+        book.place_order(Side::Sell, 100, 10);
+        clock.advance(250);
+        let entry_times: HashMap<Uuid, u64> = book.asks[&Price(100)].iter().map(|order| (order.id, order.timestamp)).collect();
+
+        let trades = book.place_order(Side::Buy, 100, 15).trades;
+        assert!(trades.iter().all(|trade| trade.timestamp == 1_300));
+
+        // Queue wait of each maker, from its entry to its fill
+        let waits: Vec<u64> = trades.iter().map(|trade| trade.timestamp - entry_times[&trade.maker_id]).collect();
+        assert_eq!(waits, vec![300, 250]);
+    }
+
+    #[test]
+    fn test_pro_rata_discipline() {
+        let build_book = || {
+            let mut book = OrderBook::new().with_level_discipline(LevelDiscipline::ProRata);
+            book.place_order(Side::Sell, 100, 10);
+            book.place_order(Side::Sell, 100, 20);
+            book.place_order(Side::Sell, 100, 30);
+            book
+        };
+
+        let mut book = build_book();
+        let trades = book.place_order(Side::Buy, 100, 30).trades;
+        assert_eq!(fills(&trades), vec![(100, 5), (100, 10), (100, 15)]);
+        let left: Vec<i32> = book.asks[&Price(100)].iter().map(|o| *o.quantity).collect();
+        assert_eq!(left, vec![5, 10, 15]);
+
+        // 7 shares as 1.17, 2.33 and 3.5: the odd unit goes to the largest
+        // remainder
+        let mut book = build_book();
+        let trades = book.place_order(Side::Buy, 100, 7).trades;
+        assert_eq!(fills(&trades), vec![(100, 1), (100, 2), (100, 4)]);
+
+        // Taking the whole level and more fills it oldest first
+        let mut book = build_book();
+        let result = book.place_order(Side::Buy, 100, 70);
+        assert_eq!(fills(&result.trades), vec![(100, 10), (100, 20), (100, 30)]);
+        assert_eq!(*result.remaining, 10);
+        assert_eq!(book.order_count(Side::Sell), 0);
+        book.assert_priority();
+    }
+
+    #[test]
+    fn test_iter_side() {
+        let mut book = OrderBook::new();
+        let first = book.place_order(Side::Buy, 99, 10).order_id;
+        let better = book.place_order(Side::Buy, 100, 20).order_id;
+        let second = book.place_order(Side::Buy, 99, 30).order_id;
+        let ask = book.place_order(Side::Sell, 102, 5).order_id;
+
+        let bids: Vec<Uuid> = book.iter_side(Side::Buy).map(|o| o.id).collect();
+        assert_eq!(bids, vec![better, first, second]);
+        let all: Vec<Uuid> = book.iter_orders().map(|o| o.id).collect();
+        assert_eq!(all, vec![better, first, second, ask]);
+
+        // Within a level the discipline decides the order
+        let book = book.with_level_discipline(LevelDiscipline::Lifo);
+        let bids: Vec<Uuid> = book.iter_side(Side::Buy).map(|o| o.id).collect();
+        assert_eq!(bids, vec![better, second, first]);
+    }
+
+    #[test]
+    fn test_get_order() {
+        let mut book = OrderBook::new();
+        let id = book.place_order(Side::Sell, 101, 50).order_id;
+        book.place_order(Side::Buy, 101, 20);
+
+        let order = book.get_order(id).unwrap();
+        assert_eq!((order.side, order.price, *order.quantity), (Side::Sell, Price(101), 30));
+        assert_eq!(order.quantity, book.asks[&Price(101)][0].quantity);
+
+        let hidden = book.place_hidden(Side::Buy, 95, 10);
+        assert_eq!(book.get_order(hidden).map(|o| *o.quantity), Some(10));
+
+        book.place_order(Side::Buy, 101, 30);
+        assert!(book.get_order(id).is_none());
+    }
+
+    #[test]
+    fn test_cumulative_depth() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 98, 30);
+        book.place_order(Side::Buy, 100, 10);
+        book.place_order(Side::Buy, 99, 20);
+        book.place_order(Side::Sell, 102, 5);
+        book.place_order(Side::Sell, 101, 15);
+
+        let bids = book.cumulative_depth(Side::Buy, 2);
+        assert_eq!(
+            bids,
+            vec![(Price(100), Quantity(10), Quantity(10)), (Price(99), Quantity(20), Quantity(30))]
+        );
+        let asks = book.cumulative_depth(Side::Sell, 5);
+        assert_eq!(
+            asks,
+            vec![(Price(101), Quantity(15), Quantity(15)), (Price(102), Quantity(5), Quantity(20))]
+        );
+    }
+
+    #[test]
+    fn test_vwap() {
+        assert_eq!(types::vwap(&[]), None);
+
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 103, 5);
+        let trades = book.place_order(Side::Buy, 103, 25).trades;
+        // 2515 / 25 = 100.6
+        assert_eq!(types::vwap(&trades), Some(Price(101)));
+        // 2010 / 20 = 100.5 rounds up
+        assert_eq!(types::vwap(&trades[..2]), Some(Price(101)));
+
+        // Notional beyond i32 doesn't overflow
+        let maker = Uuid::new_v4();
+        let large = Trade::new(Price(2_000_000), Quantity(2_000), maker, maker, Side::Buy);
+        assert_eq!(types::vwap(&[large.clone(), large]), Some(Price(2_000_000)));
+    }
+
+    #[test]
+    fn test_match_order_appends_its_trades() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 10);
+
+        // Trades already in the buffer are kept and count towards the limit
+        let earlier = Trade::new(Price(99), Quantity(1), Uuid::new_v4(), Uuid::new_v4(), Side::Buy);
+        let mut trades = vec![earlier];
+        let mut incoming = Order::new(Uuid::new_v4(), Side::Buy, Price(102), Quantity(25));
+        book.match_order(&mut incoming, Side::Sell, 3, usize::MAX, &mut trades);
+        assert_eq!(fills(&trades), vec![(99, 1), (100, 10), (101, 10)]);
+        assert_eq!(*incoming.quantity, 5);
+        assert_eq!(book.best_sell(), Some((Price(102), Quantity(10))));
     }
 
-    pub fn place_order(&mut self, side: Side, price: i32, quantity: i32) -> Vec<Trade> {
-        let mut incoming_order = Order::new(Uuid::new_v4(), side, price.into(), quantity.into());
-        let mut trades = Vec::new();
+    #[test]
+    fn test_last_trade_price() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 10);
+        assert_eq!(book.last_trade_price(), None);
 
-        match side {
-            Side::Buy => {
-                // Match against asks (sell orders)
-                self.match_order(&mut incoming_order, &mut trades, true);
-                // Add remainder to bids if any quantity left
-                if *incoming_order.quantity > 0 {
-                    self.add_order_to_book(incoming_order);
-                }
-            }
-            Side::Sell => {
-                // Match against bids (buy orders)
-                self.match_order(&mut incoming_order, &mut trades, false);
-                // Add remainder to asks if any quantity left
-                if *incoming_order.quantity > 0 {
-                    self.add_order_to_book(incoming_order);
-                }
-            }
-        }
+        book.place_order(Side::Buy, 102, 15);
+        assert_eq!(book.last_trade_price(), Some(Price(102)));
 
-        trades
+        // Orders that don't trade leave it alone
+        book.place_order(Side::Buy, 90, 10);
+        assert_eq!(book.last_trade_price(), Some(Price(102)));
+        book.place_order(Side::Sell, 90, 5);
+        assert_eq!(book.last_trade_price(), Some(Price(90)));
     }
 
-    fn match_order(&mut self, incoming_order: &mut Order, trades: &mut Vec<Trade>, matching_against_asks: bool) {
-        let opposite_book = if matching_against_asks {
-            &mut self.asks
-        } else {
-            &mut self.bids
-        };
+    #[test]
+    fn test_purge_expired() {
+        let mut book = OrderBook::new();
+        let expiring = book.place_gtd(Side::Sell, 100, 10, 1_000).order_id;
+        let later = book.place_gtd(Side::Sell, 101, 10, 5_000).order_id;
+        let plain = book.place_order(Side::Sell, 101, 10).order_id;
 
-        let mut prices_to_remove = Vec::new();
+        assert!(book.purge_expired(999).is_empty());
+        let purged: Vec<Uuid> = book.purge_expired(1_000).iter().map(|o| o.id).collect();
+        assert_eq!(purged, vec![expiring]);
+        assert!(!book.asks.contains_key(&Price(100)));
+        assert!(book.get_order(expiring).is_none());
 
-        // Get price levels in correct order
-        let price_levels: Vec<Price> = if matching_against_asks {
-            // For buy orders matching against asks: start from lowest ask price
-            opposite_book.keys().cloned().collect()
-        } else {
-            // For sell orders matching against bids: start from highest bid price
-            opposite_book.keys().cloned().collect::<Vec<_>>().into_iter().rev().collect()
-        };
+        // The purged order no longer matches
+        let trades = book.place_order(Side::Buy, 100, 10).trades;
+        assert!(trades.is_empty());
 
-        for price_level in price_levels {
-            if *incoming_order.quantity == 0 {
-                break;
-            }
+        let purged: Vec<Uuid> = book.purge_expired(u64::MAX).iter().map(|o| o.id).collect();
+        assert_eq!(purged, vec![later]);
+        let left: Vec<Uuid> = book.iter_side(Side::Sell).map(|o| o.id).collect();
+        assert_eq!(left, vec![plain]);
+    }
 
-            // Check if we can match at this price level
-            let can_match = if matching_against_asks {
-                incoming_order.price >= price_level
-            } else {
-                incoming_order.price <= price_level
-            };
+    #[test]
+    fn test_place_order_with_id() {
+        let mut book = OrderBook::new();
+        let id = Uuid::new_v4();
+        let placed = book.place_order_with_id(id, Side::Buy, 100, 10).unwrap();
+        assert_eq!(placed.order_id, id);
 
-            if !can_match {
-                break;
-            }
+        // Re-sending it is refused and leaves the book alone
+        assert_eq!(book.place_order_with_id(id, Side::Buy, 100, 10).err(), Some(OrderError::DuplicateId));
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(10))));
 
-            if let Some(order_queue) = opposite_book.get_mut(&price_level) {
-                while let Some(resting_order) = order_queue.front_mut() {
-                    if *incoming_order.quantity == 0 {
-                        break;
-                    }
+        // Once it has filled the id is free again
+        book.place_order(Side::Sell, 100, 10);
+        assert!(book.place_order_with_id(id, Side::Sell, 105, 5).is_ok());
+        assert_eq!(book.get_order(id).map(|o| o.side), Some(Side::Sell));
 
-                    let trade_quantity = (*incoming_order.quantity).min(*resting_order.quantity);
+        // A pending stop order holds on to its id too
+        let stop = book.place_stop(Side::Buy, 110, None, 5).unwrap();
+        assert_eq!(book.place_order_with_id(stop, Side::Buy, 100, 10).err(), Some(OrderError::DuplicateId));
+        assert_eq!(book.best_buy(), None);
+    }
 
-                    let trade = Trade::new(
-                        price_level.into(),
-                        trade_quantity.into(),
-                        resting_order.id,
-                        incoming_order.id,
-                    );
-                    trades.push(trade);
+    #[test]
+    fn test_display_ladder() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 102, 5);
+        book.place_order(Side::Sell, 101, 15);
+        book.place_order(Side::Buy, 99, 20);
+        book.place_order(Side::Buy, 99, 10);
 
-                    incoming_order.quantity = (*incoming_order.quantity - trade_quantity).into();
-                    resting_order.quantity = (*resting_order.quantity - trade_quantity).into();
+        let expected = [
+            "       price     quantity",
+            "         102            5",
+            "         101           15",
+            "------- spread 2 --------",
+            "          99           30",
+        ];
+        assert_eq!(book.to_string(), expected.map(|line| line.to_owned() + "\n").concat());
 
-                    if *resting_order.quantity == 0 {
-                        order_queue.pop_front();
-                    }
-                }
+        let empty = ["       price     quantity", "     no asks", "-------------------------", "     no bids"];
+        assert_eq!(OrderBook::new().to_string(), empty.map(|line| line.to_owned() + "\n").concat());
+    }
 
-                if order_queue.is_empty() {
-                    prices_to_remove.push(price_level);
-                }
-            }
-        }
+    #[test]
+    fn test_imbalance() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.imbalance(5), None);
+        book.place_order(Side::Buy, 100, 30);
+        book.place_order(Side::Buy, 99, 40);
+        // One-sided book
+        assert_eq!(book.imbalance(5), None);
 
-        // Clean up empty price levels
-        for price in prices_to_remove {
-            opposite_book.remove(&price);
-        }
+        book.place_order(Side::Sell, 101, 10);
+        book.place_order(Side::Sell, 102, 20);
+        // Top level only: (30 - 10) / 40
+        assert_eq!(book.imbalance(1), Some(0.5));
+        // Both levels: (70 - 30) / 100
+        assert_eq!(book.imbalance(2), Some(0.4));
+        assert_eq!(book.imbalance(0), None);
+
+        book.place_order(Side::Sell, 103, 100);
+        assert_eq!(book.imbalance(10), Some(-0.3));
     }
 
-    fn add_order_to_book(&mut self, order: Order) {
-        let book = match order.side {
-            Side::Buy => &mut self.bids,
-            Side::Sell => &mut self.asks,
-        };
+    #[test]
+    fn test_cancel_all_for_owner() {
+        let mut book = OrderBook::new();
+        let bid = book.place_order_as(7, Side::Buy, 99, 10).order_id;
+        let other_bid = book.place_order_as(8, Side::Buy, 99, 10).order_id;
+        let deeper_bid = book.place_order_as(7, Side::Buy, 98, 10).order_id;
+        let ask = book.place_order_as(7, Side::Sell, 101, 10).order_id;
+        let far_ask = book.place_order_as(7, Side::Sell, 105, 10).order_id;
+        let other_ask = book.place_order_as(8, Side::Sell, 103, 10).order_id;
 
-        book.entry(order.price)
-            .or_insert_with(VecDeque::new)
-            .push_back(order);
-    }
+        let cancelled: Vec<Uuid> = book.cancel_all_for_owner(7).iter().map(|o| o.id).collect();
+        assert_eq!(cancelled, vec![bid, deeper_bid, ask, far_ask]);
+        assert!(cancelled.iter().all(|&id| book.get_order(id).is_none()));
 
-    pub fn best_buy(&self) -> Option<(Price, Quantity)> {
-        // Highest bid price (last in BTreeMap)
-        self.bids.last_key_value().map(|(price, orders)| {
-            ((*price).into(), aggregate_quantity_at_price(orders))
-        })
+        // Emptied levels are gone, shared ones keep the other owner's orders
+        assert!(!book.bids.contains_key(&Price(98)));
+        assert!(!book.asks.contains_key(&Price(101)) && !book.asks.contains_key(&Price(105)));
+        let left: Vec<Uuid> = book.iter_orders().map(|o| o.id).collect();
+        assert_eq!(left, vec![other_bid, other_ask]);
+        assert!(book.cancel_all_for_owner(7).is_empty());
     }
 
-    pub fn best_sell(&self) -> Option<(Price, Quantity)> {
-        // Lowest ask price (first in BTreeMap)
-        self.asks.first_key_value().map(|(price, orders)| {
-            ((*price).into(), aggregate_quantity_at_price(orders))
-        })
-    }
-}
+    #[test]
+    fn test_market_order_max_levels() {
+        let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 50);
 
-fn aggregate_quantity_at_price(orders: &VecDeque<Order>) -> Quantity {
-    let total: i32 = orders.iter().map(|order| *order.quantity).sum();
-    total.into()
-}
+        let trades = book.place_market_order_with_max_levels(Side::Buy, 30, 1);
+        assert_eq!(fills(&trades), vec![(100, 10), (100, 10)]);
+        // The rest is dropped, not rested
+        assert_eq!(book.best_buy(), None);
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(50))));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert!(book.place_market_order_with_max_levels(Side::Buy, 10, 0).is_empty());
+        let trades = book.place_market_order_with_max_levels(Side::Buy, 10, 5);
+        assert_eq!(fills(&trades), vec![(101, 10)]);
+    }
 
     #[test]
-    fn test_empty_order_book() {
-        let book = OrderBook::new();
-        assert_eq!(book.best_buy(), None);
-        assert_eq!(book.best_sell(), None);
+    fn test_try_place_order_as_self_trade() {
+        let mut book = OrderBook::new().with_self_trade_policy(SelfTradePolicy::CancelIncoming);
+        book.place_order_as(2, Side::Sell, 100, 10);
+        book.place_order_as(1, Side::Sell, 101, 10);
+
+        // Fills entirely before reaching the owner's own ask
+        let result = book.try_place_order_as(1, Side::Buy, 101, 10).unwrap();
+        assert_eq!(fills(&result.trades), vec![(100, 10)]);
+        assert_eq!(book.try_place_order_as(1, Side::Buy, 101, 5).err(), Some(OrderError::SelfTradePrevented));
+        assert_eq!(book.best_sell(), Some((Price(101), Quantity(10))));
+        assert_eq!(book.try_place_order_as(1, Side::Buy, 101, 0).err(), Some(OrderError::NonPositiveQuantity));
+
+        // Other policies never refuse
+        let mut book = OrderBook::new().with_self_trade_policy(SelfTradePolicy::CancelResting);
+        book.place_order_as(1, Side::Sell, 100, 10);
+        assert!(book.try_place_order_as(1, Side::Buy, 100, 5).is_ok());
     }
 
     #[test]
-    fn test_simple_buy_sell_match() {
+    fn test_trade_totals() {
         let mut book = OrderBook::new();
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Sell, 101, 20);
+        book.place_order(Side::Buy, 101, 25);
+        book.place_order(Side::Buy, 99, 5);
+        book.place_order(Side::Sell, 99, 8);
+        assert_eq!(book.trade_count(), 3);
+        assert_eq!(book.total_traded_volume(), 30);
+        assert_eq!(book.stats().total_trades, 3);
 
-        // Add sell order first
-        let trades = book.place_order(Side::Sell, 100, 50);
-        assert!(trades.is_empty());
+        book.reset_stats();
+        assert_eq!((book.trade_count(), book.total_traded_volume()), (0, 0));
+        book.place_order(Side::Buy, 101, 2);
+        assert_eq!((book.trade_count(), book.total_traded_volume()), (1, 2));
+    }
 
-        // Add matching buy order
-        let trades = book.place_order(Side::Buy, 100, 30);
-        assert_eq!(trades.len(), 1);
+    #[test]
+    fn test_sequential_ids() {
+        let mut book = OrderBook::new().with_id_generator(id::SequentialIdGenerator::new(1));
+        let first = book.place_order(Side::Sell, 100, 10).order_id;
+        let stop = book.place_stop(Side::Buy, 105, None, 5).unwrap();
+        assert_eq!((first, stop), (Uuid::from_u128(1), Uuid::from_u128(2)));
 
-        let trade = &trades[0];
-        assert_eq!(*trade.price, 100);
-        assert_eq!(*trade.quantity, 30);
-        assert_ne!(trade.maker_id, trade.taker_id);
+        // A refused split doesn't use up an id
+        assert_eq!(book.split_order(first, 10), Err(SplitError::InvalidQuantity));
+        assert_eq!(book.split_order(first, 4), Ok(Uuid::from_u128(3)));
+        assert_eq!(book.place_order(Side::Buy, 90, 1).order_id, Uuid::from_u128(4));
 
-        // Check remaining sell order
-        assert_eq!(book.best_sell(), Some((Price(100), Quantity(20))));
+        // Triggering the stop and placing under a given id draw no ids either
+        book.place_order(Side::Sell, 105, 1);
+        let trades = book.place_order(Side::Buy, 105, 11).trades;
+        assert_eq!(trades.last().map(|trade| trade.price), Some(Price(105)));
+        assert!(book.snapshot().stops.is_empty());
+        assert_eq!(book.place_order_with_id(Uuid::from_u128(99), Side::Buy, 90, 1).map(|result| result.order_id), Ok(Uuid::from_u128(99)));
+        assert_eq!(book.place_order(Side::Buy, 90, 1).order_id, Uuid::from_u128(7));
     }
 
     #[test]
-    fn test_partial_fill_and_remainder() {
+    fn test_replay_events() {
+        let events = Rc::new(RefCell::new(Vec::new()));
         let mut book = OrderBook::new();
+        let sink = Rc::clone(&events);
+        book.set_event_handler(Box::new(move |event| sink.borrow_mut().push(event)));
 
-        // Add sell order
-        book.place_order(Side::Sell, 100, 30);
+        let ask = book.place_order_as(1, Side::Sell, 101, 30).order_id;
+        book.place_order(Side::Sell, 101, 10);
+        let cancelled = book.place_order(Side::Sell, 102, 10).order_id;
+        let bid = book.place_order(Side::Buy, 99, 20).order_id;
+        book.place_order(Side::Buy, 99, 5);
+        // Hidden orders stay hidden, one trading at the midpoint of 100
+        book.place_hidden(Side::Sell, 100, 3);
+        book.place_hidden(Side::Buy, 95, 7);
+        book.place_order_as(2, Side::Buy, 101, 35);
+        book.cancel_order(cancelled).unwrap();
+        book.amend_quantity(bid, 12).unwrap();
+        book.split_order(bid, 4).unwrap();
+        book.place_order(Side::Sell, 98, 6);
+        assert!(book.get_order(ask).is_none());
 
-        // Add larger buy order
-        let trades = book.place_order(Side::Buy, 105, 50);
-        assert_eq!(trades.len(), 1);
+        let replayed = OrderBook::replay(events.borrow().iter().cloned());
+        let (original, rebuilt) = (book.snapshot(), replayed.snapshot());
+        assert_eq!(rebuilt.bids, original.bids);
+        assert_eq!(rebuilt.asks, original.asks);
+        assert_eq!(rebuilt.hidden_bids, original.hidden_bids);
+        assert_eq!(rebuilt.hidden_asks, original.hidden_asks);
+        assert_eq!(replayed.best_buy(), book.best_buy());
+        assert_eq!(replayed.depth(5), book.depth(5));
+        assert_eq!((replayed.position(1), replayed.position(2)), (-30, 35));
+        assert_eq!(replayed.trade_count(), book.trade_count());
+        assert_eq!(replayed.last_trade_price(), book.last_trade_price());
+        let sizes: Vec<i32> = replayed.iter_side(Side::Buy).map(|o| *o.quantity).collect();
+        assert_eq!(sizes, vec![6, 5]);
 
-        let trade = &trades[0];
-        assert_eq!(*trade.price, 100); // Trade at resting order price
-        assert_eq!(*trade.quantity, 30);
+        // In the opening auction both filled orders were resting
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut book = OrderBook::new().with_session_state(SessionState::PreOpen);
+        let sink = Rc::clone(&events);
+        book.set_event_handler(Box::new(move |event| sink.borrow_mut().push(event)));
+        book.place_order(Side::Buy, 101, 10);
+        book.place_order(Side::Buy, 100, 4);
+        book.place_order(Side::Sell, 99, 6);
+        book.place_order(Side::Sell, 100, 6);
+        let (_, trades) = book.open_from_auction();
+        assert_eq!(fills(&trades), vec![(100, 6), (100, 4), (100, 2)]);
 
-        // Check remainder buy order was added
-        assert_eq!(book.best_buy(), Some((Price(105), Quantity(20))));
-        assert_eq!(book.best_sell(), None);
+        let replayed = OrderBook::replay(events.borrow().iter().cloned());
+        assert_eq!(replayed.snapshot().bids, book.snapshot().bids);
+        assert_eq!(replayed.snapshot().asks, book.snapshot().asks);
+        assert_eq!(replayed.best_buy(), Some((Price(100), Quantity(2))));
+        assert_eq!(replayed.best_sell(), None);
     }
 
     #[test]
-    fn test_price_time_priority() {
+    fn test_quantity_at_price() {
         let mut book = OrderBook::new();
+        book.place_order(Side::Buy, 99, 10);
+        book.place_order(Side::Buy, 99, 15);
+        book.place_order(Side::Sell, 101, 7);
 
-        // Add multiple sell orders at same price
-        book.place_order(Side::Sell, 100, 10); // First (oldest)
-        book.place_order(Side::Sell, 100, 20); // Second
-        book.place_order(Side::Sell, 100, 15); // Third
-
-        // Buy order that matches all
-        let trades = book.place_order(Side::Buy, 100, 45);
-        assert_eq!(trades.len(), 3);
+        assert_eq!(book.quantity_at_price(Side::Buy, 99), Quantity(25));
+        assert_eq!(book.quantity_at_price(Side::Sell, 101), Quantity(7));
+        assert_eq!(book.quantity_at_price(Side::Buy, 100), Quantity(0));
+        assert_eq!(book.quantity_at_price(Side::Sell, 99), Quantity(0));
 
-        // Check time priority - oldest order first
-        assert_eq!(*trades[0].quantity, 10);
-        assert_eq!(*trades[1].quantity, 20);
-        assert_eq!(*trades[2].quantity, 15);
+        book.place_order(Side::Sell, 99, 12);
+        assert_eq!(book.quantity_at_price(Side::Buy, 99), Quantity(13));
     }
 
     #[test]
-    fn test_price_priority() {
+    fn test_amend_to_zero_cancels() {
         let mut book = OrderBook::new();
+        let lone = book.place_order(Side::Buy, 99, 10).order_id;
+        let first = book.place_order(Side::Buy, 100, 10).order_id;
+        let second = book.place_order(Side::Buy, 100, 20).order_id;
 
-        // Add buy orders at different prices
-        book.place_order(Side::Buy, 98, 10);   // Lower price
-        book.place_order(Side::Buy, 102, 15);  // Higher price (best)
-        book.place_order(Side::Buy, 100, 20);  // Middle price
+        book.amend_quantity(lone, 0).unwrap();
+        assert!(book.get_order(lone).is_none());
+        assert!(!book.bids.contains_key(&Price(99)));
+        assert_eq!(book.amend_quantity(lone, 0), Err(OrderError::OrderNotFound));
 
-        // Sell order matches with highest bid first
-        let trades = book.place_order(Side::Sell, 98, 50);
-        assert_eq!(trades.len(), 3);
+        book.amend_quantity(first, 0).unwrap();
+        let left: Vec<Uuid> = book.iter_side(Side::Buy).map(|o| o.id).collect();
+        assert_eq!(left, vec![second]);
 
-        // Check price priority - highest bid first
-        assert_eq!(*trades[0].price, 102);
-        assert_eq!(*trades[1].price, 100);
-        assert_eq!(*trades[2].price, 98);
+        // Empty orders never rest, even before the open
+        let mut book = OrderBook::new().with_session_state(SessionState::PreOpen);
+        assert_eq!(*book.place_order(Side::Buy, 100, 0).remaining, 0);
+        assert_eq!(book.best_buy(), None);
     }
 
     #[test]
-    fn test_no_match_different_prices() {
-        let mut book = OrderBook::new();
+    fn test_fill_listener() {
+        let listener = RecordingListener::default();
+        let mut book = OrderBook::new().with_listener(listener.clone());
+        let first = book.place_order(Side::Sell, 100, 10).order_id;
+        let second = book.place_order(Side::Sell, 101, 20).order_id;
 
-        // Add sell order at high price
-        book.place_order(Side::Sell, 105, 10);
+        book.place_order(Side::Buy, 101, 15);
+        book.place_order(Side::Buy, 101, 15);
+        assert_eq!(
+            *listener.resting_fills.borrow(),
+            vec![
+                (first, Quantity(10), Quantity(0)),
+                (second, Quantity(5), Quantity(15)),
+                (second, Quantity(15), Quantity(0)),
+            ]
+        );
 
-        // Add buy order at lower price - no match
-        let trades = book.place_order(Side::Buy, 95, 10);
-        assert!(trades.is_empty());
+        // Auction trades fill resting orders on both sides
+        listener.resting_fills.borrow_mut().clear();
+        let mut book = OrderBook::new()
+            .with_session_state(SessionState::PreOpen)
+            .with_listener(listener.clone());
+        let bid = book.place_order(Side::Buy, 101, 15).order_id;
+        let ask = book.place_order(Side::Sell, 100, 10).order_id;
+        book.open_from_auction();
+        assert_eq!(
+            *listener.resting_fills.borrow(),
+            vec![(ask, Quantity(10), Quantity(0)), (bid, Quantity(10), Quantity(5))]
+        );
+    }
 
-        // Both orders should remain in book
-        assert_eq!(book.best_buy(), Some((Price(95), Quantity(10))));
-        assert_eq!(book.best_sell(), Some((Price(105), Quantity(10))));
+    #[test]
+    fn test_clear() {
+        let mut book = OrderBook::new().with_tick_size(5).with_trade_history(10);
+        book.place_order(Side::Sell, 100, 10);
+        book.place_order(Side::Buy, 100, 4);
+        book.place_order(Side::Buy, 95, 10);
+        let hidden = book.place_hidden(Side::Buy, 90, 10);
+        book.place_stop(Side::Buy, 110, None, 5).unwrap();
+
+        book.clear();
+        assert_eq!((book.best_buy(), book.best_sell()), (None, None));
+        assert_eq!((book.trade_count(), book.total_traded_volume()), (0, 0));
+        assert_eq!(book.last_trade_price(), None);
+        assert!(book.tape().is_empty() && book.get_order(hidden).is_none());
+        assert_eq!(book.snapshot(), OrderBook::new().snapshot());
+
+        // Settings survive, and a trade no longer sets off the stop
+        assert_eq!(book.try_place_order(Side::Sell, 103, 1).err(), Some(OrderError::InvalidTick));
+        book.place_order(Side::Sell, 110, 5);
+        book.place_order(Side::Buy, 110, 2);
+        assert_eq!(book.best_sell(), Some((Price(110), Quantity(3))));
     }
 
     #[test]
-    fn test_multiple_price_levels() {
+    fn test_clear_is_replayed() {
+        let events = Rc::new(RefCell::new(Vec::new()));
         let mut book = OrderBook::new();
+        let sink = Rc::clone(&events);
+        book.set_event_handler(Box::new(move |event| sink.borrow_mut().push(event)));
+        book.place_order(Side::Sell, 100, 10);
+        book.place_hidden(Side::Buy, 90, 10);
 
-        // Build order book with multiple levels
+        book.clear();
+        assert_eq!(events.borrow().last(), Some(&BookEvent::Cleared));
+
+        book.place_order(Side::Buy, 95, 5);
+        let replayed = OrderBook::replay(events.borrow().iter().cloned());
+        let (original, rebuilt) = (book.snapshot(), replayed.snapshot());
+        assert_eq!(rebuilt.bids, original.bids);
+        assert_eq!((rebuilt.asks, rebuilt.hidden_bids), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_would_cross() {
+        let mut book = OrderBook::new();
+        assert!(!book.would_cross(Side::Buy, i64::MAX));
         book.place_order(Side::Sell, 101, 10);
-        book.place_order(Side::Sell, 102, 15);
-        book.place_order(Side::Sell, 103, 20);
+        book.place_order(Side::Buy, 99, 10);
+
+        assert!(book.would_cross(Side::Buy, 101));
+        assert!(book.would_cross(Side::Buy, 105));
+        assert!(!book.would_cross(Side::Buy, 100));
+        assert!(book.would_cross(Side::Sell, 99));
+        assert!(!book.would_cross(Side::Sell, 100));
 
+        let mut book = OrderBook::new().with_equal_price_crosses(false);
+        book.place_order(Side::Sell, 101, 10);
+        assert!(!book.would_cross(Side::Buy, 101));
+        assert!(book.would_cross(Side::Buy, 102));
+    }
+
+    #[test]
+    fn test_trade_pricing_policy() {
+        let trade_price = |policy, resting_side, resting, limit| {
+            let mut book = OrderBook::new().with_trade_pricing_policy(policy);
+            book.place_order(resting_side, resting, 10);
+            let taker_side = if resting_side == Side::Sell { Side::Buy } else { Side::Sell };
+            book.place_order(taker_side, limit, 10).trades[0].price
+        };
+
+        assert_eq!(trade_price(TradePricingPolicy::MakerPrice, Side::Sell, 100, 105), Price(100));
+        assert_eq!(trade_price(TradePricingPolicy::TakerPrice, Side::Sell, 100, 105), Price(105));
+        // 102.5 rounds towards the resting ask, in the buyer's favour
+        assert_eq!(trade_price(TradePricingPolicy::Midpoint, Side::Sell, 100, 105), Price(102));
+        assert_eq!(trade_price(TradePricingPolicy::Midpoint, Side::Sell, 100, 104), Price(102));
+        // ... and towards the resting bid for a sell
+        assert_eq!(trade_price(TradePricingPolicy::Midpoint, Side::Buy, 105, 100), Price(103));
+
+        // Market orders trade at the resting price
+        let mut book = OrderBook::new().with_trade_pricing_policy(TradePricingPolicy::Midpoint);
+        book.place_order(Side::Sell, 100, 10);
+        assert_eq!(fills(&book.place_market_order(Side::Buy, 5)), vec![(100, 5)]);
+    }
+
+    #[test]
+    fn test_order_count_at_price() {
+        let mut book = OrderBook::new();
         book.place_order(Side::Buy, 99, 10);
-        book.place_order(Side::Buy, 98, 15);
+        book.place_order(Side::Buy, 99, 15);
+        book.place_order(Side::Sell, 101, 7);
 
-        // Large buy order crosses spread
-        let trades = book.place_order(Side::Buy, 102, 30);
-        assert_eq!(trades.len(), 2);
+        assert_eq!(book.order_count_at_price(Side::Buy, 99), 2);
+        assert_eq!(book.order_count_at_price(Side::Sell, 101), 1);
+        assert_eq!(book.order_count_at_price(Side::Buy, 101), 0);
 
-        // Should match 101 level completely, then completely match 102 level
-        assert_eq!(*trades[0].price, 101);
-        assert_eq!(*trades[0].quantity, 10);
-        assert_eq!(*trades[1].price, 102);
-        assert_eq!(*trades[1].quantity, 15);
+        book.place_order(Side::Sell, 99, 12);
+        assert_eq!(book.order_count_at_price(Side::Buy, 99), 1);
+        book.place_order(Side::Sell, 99, 13);
+        assert_eq!(book.order_count_at_price(Side::Buy, 99), 0);
+    }
 
-        // Check remaining book state
-        assert_eq!(book.best_sell(), Some((Price(103), Quantity(20)))); // 103 level remains untouched
-        assert_eq!(book.best_buy(), Some((Price(102), Quantity(5)))); // remainder of incoming buy order
+    #[test]
+    fn test_with_capacity() {
+        let mut book = OrderBook::with_capacity(8);
+        assert_eq!(book.spare_levels.len(), 8);
+
+        // New levels take their queues from the pool
+        book.place_order(Side::Buy, 99, 10);
+        book.place_order(Side::Sell, 101, 10);
+        assert_eq!(book.spare_levels.len(), 6);
+        book.place_order(Side::Buy, 101, 10);
+        assert_eq!(book.spare_levels.len(), 7);
+        assert_eq!(book.best_buy(), Some((Price(99), Quantity(10))));
     }
 
     #[test]
-    fn test_best_buy_sell_aggregation() {
+    fn test_spare_level_pool_is_bounded() {
         let mut book = OrderBook::new();
+        for price in 1..=100 {
+            book.place_order(Side::Sell, price, 1);
+        }
+        book.place_order(Side::Buy, 100, 100);
+        assert_eq!(book.spare_levels.len(), SPARE_LEVEL_LIMIT);
 
-        // Add multiple orders at same price level
-        book.place_order(Side::Buy, 100, 10);
-        book.place_order(Side::Buy, 100, 20);
-        book.place_order(Side::Buy, 100, 15);
+        // A level that grew large is shrunk before it is kept
+        let mut book = OrderBook::new();
+        for _ in 0..1000 {
+            book.place_order(Side::Sell, 100, 1);
+        }
+        book.place_order(Side::Buy, 100, 1000);
+        assert_eq!(book.spare_levels.len(), 1);
+        assert!(book.spare_levels[0].capacity() <= SPARE_LEVEL_MAX_CAPACITY);
 
-        book.place_order(Side::Sell, 105, 25);
-        book.place_order(Side::Sell, 105, 30);
+        // Explicitly reserved queues aren't limited
+        assert_eq!(OrderBook::with_capacity(100).spare_levels.len(), 100);
+    }
 
-        // Check aggregated quantities
-        assert_eq!(book.best_buy(), Some((Price(100), Quantity(45)))); // 10 + 20 + 15
-        assert_eq!(book.best_sell(), Some((Price(105), Quantity(55)))); // 25 + 30
+    #[test]
+    fn test_side_opposite() {
+        assert_eq!(Side::Buy.opposite(), Side::Sell);
+        assert_eq!(Side::Sell.opposite(), Side::Buy);
+        assert_eq!(Side::Buy.opposite().opposite(), Side::Buy);
     }
 
     #[test]
-    fn test_realistic_trading_scenario() {
+    fn test_eight_decimal_prices() {
+        // 65000.12345678 needs more room than an i32 has
+        let mut book = OrderBook::new().with_price_decimals(8);
+        book.place_order(Side::Sell, 6_500_012_345_679, 3);
+        book.place_order(Side::Sell, 6_500_012_345_678, 2);
+        book.place_order(Side::Buy, 6_500_000_000_000, 4);
+        assert_eq!(book.best_sell(), Some((Price(6_500_012_345_678), Quantity(2))));
+        assert_eq!(book.spread(), Some(Price(12_345_678)));
+        assert_eq!(book.to_json_l2(1), r#"{"bids":[[65000.00000000,4]],"asks":[[65000.12345678,2]],"seq":3}"#);
+
+        let trades = book.place_order(Side::Buy, 6_500_012_345_679, 4).trades;
+        assert_eq!(fills(&trades), vec![(6_500_012_345_678, 2), (6_500_012_345_679, 2)]);
+        assert_eq!(types::vwap(&trades), Some(Price(6_500_012_345_679)));
+
+        // Prices beyond what an f64 holds exactly keep every digit
+        let mut book = OrderBook::new().with_price_decimals(8);
+        book.place_order(Side::Sell, i64::MAX, 1);
+        assert_eq!(book.to_json_l2(1), r#"{"bids":[],"asks":[[92233720368.54775807,1]],"seq":1}"#);
+    }
+
+    #[test]
+    fn test_price_from_i32_is_whole_units() {
+        assert_eq!(Price::from(100), Price(100));
+        assert_eq!(Price::from(i32::MIN), Price(-2_147_483_648));
+    }
+
+    #[test]
+    fn test_positions_follow_fills() {
         let mut book = OrderBook::new();
+        book.place_order_as(8, Side::Sell, 100, 30);
+        book.place_order_as(7, Side::Buy, 100, 20);
+        book.place_order(Side::Buy, 100, 10);
+        assert_eq!(book.position(7), 20);
+        assert_eq!(book.position(8), -30);
+        assert_eq!(book.position(0), 0);
+        assert_eq!(book.position(9), 0);
+    }
 
-        // Build realistic order book
-        // Sell side
-        book.place_order(Side::Sell, 105, 100);
-        book.place_order(Side::Sell, 104, 200);
-        book.place_order(Side::Sell, 103, 150);
+    #[test]
+    fn test_reduce_only_clamps_to_position() {
+        let mut book = OrderBook::new();
+        book.place_order_as(8, Side::Sell, 100, 50);
+        book.place_order_as(7, Side::Buy, 100, 50);
+        assert_eq!(book.position(7), 50);
 
-        // Buy side
-        book.place_order(Side::Buy, 102, 180);
-        book.place_order(Side::Buy, 101, 220);
-        book.place_order(Side::Buy, 100, 300);
+        // Long 50: a reduce-only sell of 80 only submits 50
+        let result = book.place_reduce_only(7, Side::Sell, 105, 80).unwrap();
+        assert_eq!(result.remaining, Quantity(50));
+        assert_eq!(book.best_sell(), Some((Price(105), Quantity(50))));
 
-        // Spread should be 102 bid, 103 ask
-        assert_eq!(book.best_buy(), Some((Price(102), Quantity(180))));
-        assert_eq!(book.best_sell(), Some((Price(103), Quantity(150))));
+        // The resting sell already covers the whole position
+        assert_eq!(book.place_reduce_only(7, Side::Sell, 106, 10).err(), Some(OrderError::NoPositionToReduce));
+        // A long position can't be reduced by buying
+        assert_eq!(book.place_reduce_only(7, Side::Buy, 99, 10).err(), Some(OrderError::NoPositionToReduce));
 
-        // Large market order crosses spread
-        let trades = book.place_order(Side::Buy, 106, 500);
+        // Filling the reduce-only order takes the position flat, not short
+        book.place_order(Side::Buy, 105, 80);
+        assert_eq!(book.position(7), 0);
+    }
 
-        // Should execute against all ask levels
-        assert_eq!(trades.len(), 3);
+    #[test]
+    fn test_maker_spread_capture() {
+        let mut book = OrderBook::new().with_trade_history(16);
+        book.place_order_as(5, Side::Buy, 98, 10);
+        book.place_order_as(5, Side::Sell, 102, 10);
+        book.place_order_as(6, Side::Sell, 103, 10);
 
-        let total_traded: i32 = trades.iter().map(|t| *t.quantity).sum();
-        assert_eq!(total_traded, 450); // 150 + 200 + 100
+        // Mid 100: owner 5 sells 10 at 102, owner 6 sells 5 at 103
+        book.place_order(Side::Buy, 103, 15);
+        assert_eq!(book.maker_spread_capture(5), 20);
+        assert_eq!(book.maker_spread_capture(6), 15);
 
-        // Check final state - buy order remainder should be in book
-        assert_eq!(book.best_buy(), Some((Price(106), Quantity(50)))); // 500 - 450 = 50 remaining
+        // Mid now 100.5: owner 5 buys 4 at 98, 2.5 below it
+        book.place_order(Side::Sell, 98, 4);
+        assert_eq!(book.maker_spread_capture(5), 30);
+        assert_eq!(book.maker_spread_capture(7), 0);
+        assert_eq!(book.maker_spread_capture(0), 0);
+    }
+
+    #[test]
+    fn test_market_snapshot_all() {
+        let mut market = market::Market::new();
+        market.add_book("EMPTY", OrderBook::new());
+
+        let mut quoted = OrderBook::new();
+        quoted.place_order(Side::Buy, 99, 10);
+        quoted.place_order(Side::Buy, 98, 5);
+        quoted.place_order(Side::Sell, 101, 7);
+        market.add_book("QUOTED", quoted);
+
+        let mut traded = OrderBook::new();
+        traded.place_order(Side::Sell, 50, 10);
+        traded.place_order(Side::Buy, 50, 4);
+        market.add_book("TRADED", traded);
+
+        let snapshot = market.snapshot_all(1);
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot["EMPTY"], MarketData::default());
+        assert_eq!(
+            snapshot["QUOTED"],
+            MarketData {
+                bids: vec![(Price(99), Quantity(10))],
+                asks: vec![(Price(101), Quantity(7))],
+                last_trade_price: None,
+                sequence: 3,
+            }
+        );
+        assert_eq!(
+            snapshot["TRADED"],
+            MarketData { bids: vec![], asks: vec![(Price(50), Quantity(6))], last_trade_price: Some(Price(50)), sequence: 2 }
+        );
+
+        // Books keep matching independently
+        market.book_mut("QUOTED").unwrap().place_order(Side::Sell, 99, 10);
+        assert_eq!(market.snapshot_all(1)["QUOTED"].last_trade_price, Some(Price(99)));
+        assert_eq!(market.book("TRADED").unwrap().last_trade_price(), Some(Price(50)));
+    }
+
+    #[test]
+    fn test_sublot_remainder_policy() {
+        let build_book = |policy| {
+            let mut book = OrderBook::new().with_lot_size(10).with_sublot_remainder_policy(policy);
+            // Unvalidated, so an odd lot of 5 rests
+            book.place_order(Side::Sell, 100, 5);
+            book
+        };
+
+        // A whole-lot buy fills the 5 and is left with a sub-lot 5
+        let mut book = build_book(SublotRemainderPolicy::Cancel);
+        let result = book.try_place_order(Side::Buy, 100, 10).unwrap();
+        assert_eq!(fills(&result.trades), vec![(100, 5)]);
+        assert_eq!(result.remaining, Quantity(0));
+        assert_eq!(book.best_buy(), None);
+
+        // Whole lots of a larger remainder still rest
+        let mut book = build_book(SublotRemainderPolicy::Cancel);
+        assert_eq!(book.try_place_order(Side::Buy, 100, 30).unwrap().remaining, Quantity(20));
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(20))));
+
+        let mut book = build_book(SublotRemainderPolicy::Rest);
+        let result = book.try_place_order(Side::Buy, 100, 10).unwrap();
+        assert_eq!(result.remaining, Quantity(5));
+        assert_eq!(book.best_buy(), Some((Price(100), Quantity(5))));
+
+        // The default is to cancel
+        let mut book = OrderBook::new().with_lot_size(10);
+        book.place_order(Side::Sell, 100, 5);
+        book.place_order(Side::Buy, 100, 10);
+        assert_eq!(book.best_buy(), None);
     }
-}
\ No newline at end of file
+}
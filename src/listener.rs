@@ -0,0 +1,22 @@
+use crate::types::{Order, Price, Quantity, Side};
+use std::fmt::Debug;
+
+/// Receives notifications about changes to the book as they happen. Every
+/// method has an empty default so implementors only override what they need.
+pub trait BookListener: Debug {
+    /// A price level was removed after matching consumed all of its orders
+    fn on_level_cleared(&mut self, _side: Side, _price: Price) {}
+
+    /// An order, or the remainder of one after matching, was queued in the
+    /// book
+    fn on_order_rested(&mut self, _order: &Order) {}
+
+    /// The worst level on a side was evicted to stay within the configured
+    /// level cap, cancelling these orders
+    fn on_orders_evicted(&mut self, _side: Side, _price: Price, _orders: &[Order]) {}
+
+    /// A resting order traded `filled` against an incoming order, or in the
+    /// opening auction, where both orders of a trade are resting. `order`
+    /// already shows what is left of it, 0 once it has fully filled.
+    fn on_fill(&mut self, _order: &Order, _filled: Quantity) {}
+}
@@ -0,0 +1,37 @@
+use crate::{OrderBook, types::MarketData};
+use std::collections::HashMap;
+
+/// Order books for several symbols, each matching on its own
+#[derive(Debug, Default)]
+pub struct Market {
+    books: HashMap<String, OrderBook>,
+}
+
+impl Market {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lists `book` under `symbol`, returning the book it replaces, if any
+    pub fn add_book(&mut self, symbol: impl Into<String>, book: OrderBook) -> Option<OrderBook> {
+        self.books.insert(symbol.into(), book)
+    }
+
+    pub fn book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.books.get(symbol)
+    }
+
+    pub fn book_mut(&mut self, symbol: &str) -> Option<&mut OrderBook> {
+        self.books.get_mut(symbol)
+    }
+
+    /// Market data with the top `levels` of every book, by symbol, for a
+    /// full state dump to a newly connected client. Nothing can trade while
+    /// the market is borrowed, so all books are seen at the same point.
+    pub fn snapshot_all(&self, levels: usize) -> HashMap<String, MarketData> {
+        self.books
+            .iter()
+            .map(|(symbol, book)| (symbol.clone(), book.market_data(levels)))
+            .collect()
+    }
+}
@@ -1,24 +1,35 @@
 use std::ops::Deref;
 use uuid::Uuid;
 
+/// A price as a 64-bit integer number of the book's smallest price units:
+/// whole units by default, or fixed-point with
+/// `OrderBook::with_price_decimals`. Wide enough for 8 decimal places at the
+/// prices crypto pairs trade at. Ordering is that of the integers, so price
+/// levels sort the same whatever the decimals.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Price(pub i32);
+pub struct Price(pub i64);
 
 impl Deref for Price {
-    type Target = i32;
+    type Target = i64;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
+impl From<i64> for Price {
+    fn from(value: i64) -> Self {
+        Price(value)
+    }
+}
+
 impl From<i32> for Price {
     fn from(value: i32) -> Self {
-        Price(value)
+        Price(i64::from(value))
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Quantity(pub i32);
 
 impl Deref for Quantity {
@@ -41,12 +52,134 @@ pub enum Side {
     Sell,
 }
 
+impl Side {
+    /// The other side, the one an order on this side trades against
+    pub const fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+/// Trading phase of the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionState {
+    /// Orders are collected without matching, ready for the opening auction
+    PreOpen,
+    /// Continuous trading, incoming orders match immediately
+    #[default]
+    Open,
+}
+
+/// Where the unfilled remainder of a partially matched order rests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RemainderRestPolicy {
+    /// At the order's own limit price
+    #[default]
+    AtLimit,
+    /// At the last price it traded at. That level was just emptied by the
+    /// order itself, so this is the most passive price that still doesn't
+    /// cross the book.
+    AtLastFillPrice,
+}
+
+/// What happens to the part of a traded order's remainder that is less than
+/// a whole lot, see `OrderBook::with_lot_size`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SublotRemainderPolicy {
+    /// Rest only the whole lots and drop the odd quantity
+    #[default]
+    Cancel,
+    /// Rest the remainder as it is
+    Rest,
+}
+
+/// Which order at a price level an incoming order fills against first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LevelDiscipline {
+    /// Oldest order first, standard time priority
+    #[default]
+    Fifo,
+    /// Newest order first
+    Lifo,
+    /// An incoming order smaller than the level is shared across all its
+    /// orders in proportion to their size. Each order gets
+    /// `floor(incoming * size / level total)` and the units that leaves
+    /// over go one each to the orders with the largest remainders, ties to
+    /// the older order. An incoming order that takes the whole level fills
+    /// oldest first.
+    ProRata,
+}
+
+/// What happens when an incoming order would trade against a resting order
+/// of the same owner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelfTradePolicy {
+    /// Cancel the resting order and keep matching past it
+    CancelResting,
+    /// Cancel what is left of the incoming order
+    CancelIncoming,
+    /// Let the orders trade
+    #[default]
+    Allow,
+}
+
+/// Price a crossing trade executes at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TradePricingPolicy {
+    /// The resting order's price
+    #[default]
+    MakerPrice,
+    /// The incoming order's limit, giving the resting order the improvement
+    TakerPrice,
+    /// Halfway between the two. An odd gap rounds towards the resting
+    /// order's price, so the half unit goes to the incoming order.
+    Midpoint,
+}
+
+impl TradePricingPolicy {
+    /// Price of a trade between a resting order at `maker_price` and an
+    /// incoming order on `taker_side` limited at `taker_limit`
+    pub fn trade_price(self, taker_side: Side, maker_price: Price, taker_limit: Price) -> Price {
+        match self {
+            TradePricingPolicy::MakerPrice => maker_price,
+            TradePricingPolicy::TakerPrice => taker_limit,
+            TradePricingPolicy::Midpoint => {
+                let sum = i128::from(*maker_price) + i128::from(*taker_limit);
+                let midpoint = match taker_side {
+                    // The resting ask is the lower price, the bid the higher
+                    Side::Buy => sum.div_euclid(2),
+                    Side::Sell => (sum + 1).div_euclid(2),
+                };
+                Price(midpoint as i64)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Order {
     pub id: Uuid,
     pub side: Side,
     pub price: Price,
     pub quantity: Quantity,
+    /// Time the order entered the book, as reported by the book's clock
+    pub timestamp: u64,
+    /// Book sequence number at arrival, never decreasing along a level's
+    /// queue
+    pub sequence: u64,
+    /// Account the order belongs to, for self-trade prevention. 0 means no
+    /// owner, and such orders never count as trading with themselves.
+    pub owner: u64,
+    /// Visible slice size of an iceberg order, 0 for a plain order
+    pub display_size: Quantity,
+    /// Iceberg quantity not yet shown, released a slice at a time as the
+    /// visible `quantity` fills
+    pub reserve: Quantity,
+    /// Time from which a good-till-date order counts as expired, `None` for
+    /// an order that rests until cancelled
+    pub expires_at: Option<u64>,
 }
 
 impl Order {
@@ -56,6 +189,88 @@ impl Order {
             side,
             price,
             quantity,
+            timestamp: 0,
+            sequence: 0,
+            owner: 0,
+            display_size: Quantity(0),
+            reserve: Quantity(0),
+            expires_at: None,
+        }
+    }
+
+    pub const fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub const fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    pub const fn with_owner(mut self, owner: u64) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    pub const fn with_expiry(mut self, expires_at: u64) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Whether a good-till-date order has expired by `now`
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Makes the order an iceberg showing at most `display_size` at a time.
+    /// Applied when the order rests, see `hide_reserve`.
+    pub const fn with_display_size(mut self, display_size: Quantity) -> Self {
+        self.display_size = display_size;
+        self
+    }
+
+    /// Splits an iceberg's quantity into its first visible slice, with the
+    /// rest kept back in the reserve
+    pub fn hide_reserve(&mut self) {
+        if *self.display_size > 0 && self.quantity > self.display_size {
+            self.reserve = Quantity(*self.reserve + *self.quantity - *self.display_size);
+            self.quantity = self.display_size;
+        }
+    }
+
+    /// Shows the next slice of an iceberg's reserve once the visible
+    /// quantity has filled. Returns false if there was no reserve left.
+    pub fn replenish(&mut self) -> bool {
+        if *self.reserve <= 0 {
+            return false;
+        }
+        let slice = (*self.reserve).min(*self.display_size);
+        self.quantity = Quantity(*self.quantity + slice);
+        self.reserve = Quantity(*self.reserve - slice);
+        true
+    }
+}
+
+/// An order held outside the book until the market trades at or through
+/// its trigger price, see `OrderBook::place_stop`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopOrder {
+    pub id: Uuid,
+    pub side: Side,
+    pub trigger: Price,
+    /// Limit of the order entered on triggering, `None` for a market order
+    pub limit: Option<Price>,
+    pub quantity: Quantity,
+}
+
+impl StopOrder {
+    /// Whether a trade at `price` activates the stop: at or above the
+    /// trigger for a buy stop, at or below it for a sell stop
+    pub fn is_triggered_by(&self, price: Price) -> bool {
+        match self.side {
+            Side::Buy => price >= self.trigger,
+            Side::Sell => price <= self.trigger,
         }
     }
 }
@@ -66,15 +281,148 @@ pub struct Trade {
     pub quantity: Quantity,
     pub maker_id: Uuid,
     pub taker_id: Uuid,
+    /// Side of the taker, the incoming order that triggered the trade
+    pub taker_side: Side,
+    /// Time the trade executed, as reported by the book's clock
+    pub timestamp: u64,
+    /// Owner of the resting order, 0 if it had none
+    pub maker_owner: u64,
+    /// Owner of the incoming order, 0 if it had none
+    pub taker_owner: u64,
 }
 
 impl Trade {
-    pub fn new(price: Price, quantity: Quantity, maker_id: Uuid, taker_id: Uuid) -> Self {
+    pub fn new(price: Price, quantity: Quantity, maker_id: Uuid, taker_id: Uuid, taker_side: Side) -> Self {
         Trade {
             price,
             quantity,
             maker_id,
             taker_id,
+            taker_side,
+            timestamp: 0,
+            maker_owner: 0,
+            taker_owner: 0,
         }
     }
-}
\ No newline at end of file
+
+    pub const fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub const fn with_maker_owner(mut self, owner: u64) -> Self {
+        self.maker_owner = owner;
+        self
+    }
+
+    pub const fn with_taker_owner(mut self, owner: u64) -> Self {
+        self.taker_owner = owner;
+        self
+    }
+}
+
+/// Volume-weighted average price of `trades`, rounded to the nearest price
+/// unit with halves rounded up. `None` if no quantity traded.
+pub fn vwap(trades: &[Trade]) -> Option<Price> {
+    let (notional, volume) = trades.iter().fold((0i128, 0i128), |(notional, volume), trade| {
+        let quantity = i128::from(*trade.quantity);
+        (notional + i128::from(*trade.price) * quantity, volume + quantity)
+    });
+    (volume > 0).then(|| Price((2 * notional + volume).div_euclid(2 * volume) as i64))
+}
+
+/// An operation on the book, as recorded in a command log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Place { side: Side, price: i64, quantity: i32 },
+    /// Cancels the oldest order at a level. Addressed by price rather than
+    /// id so that logs stay valid when replayed into a fresh book.
+    CancelFront { side: Side, price: i64 },
+}
+
+/// Snapshot of the book's size and activity, see `OrderBook::stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BookStats {
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    pub bid_orders: usize,
+    pub ask_orders: usize,
+    pub bid_volume: u64,
+    pub ask_volume: u64,
+    pub best_bid: Option<(Price, Quantity)>,
+    pub best_ask: Option<(Price, Quantity)>,
+    /// Trades executed over the book's lifetime
+    pub total_trades: u64,
+    /// Quantity traded over the book's lifetime
+    pub total_matched_volume: u64,
+}
+
+/// Top of a book for publishing, see `OrderBook::market_data`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MarketData {
+    /// Best bids first
+    pub bids: Vec<(Price, Quantity)>,
+    /// Best asks first
+    pub asks: Vec<(Price, Quantity)>,
+    pub last_trade_price: Option<Price>,
+    /// Book sequence number the data was taken at
+    pub sequence: u64,
+}
+
+/// Acknowledgement of a placed order, see `OrderBook::place_order_ack`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ack {
+    pub id: Uuid,
+    /// Price the unfilled remainder rests at, `None` if nothing rested
+    pub resting_price: Option<Price>,
+    /// Orders ahead of the remainder at its level, 0 being the front
+    pub queue_position: Option<usize>,
+    /// Quantity executed on entry
+    pub filled: Quantity,
+}
+
+/// Outcome of `OrderBook::place_order`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaceResult {
+    /// Id assigned to the incoming order, which its remainder rests under
+    pub order_id: Uuid,
+    pub trades: Vec<Trade>,
+    /// Quantity left resting in the book, 0 if nothing rested
+    pub remaining: Quantity,
+    /// Unfilled quantity the book refused to rest: below the side's minimum
+    /// quote size, an odd lot dropped by the sub-lot remainder policy, or
+    /// evicted along with its level by the level cap. 0 when everything
+    /// left over rested.
+    pub discarded: Quantity,
+}
+
+/// Every resting order of a book in priority order, best level first and
+/// queue order within a level, and the stop orders waiting for their
+/// trigger, see `OrderBook::snapshot`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BookSnapshot {
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+    pub hidden_bids: Vec<Order>,
+    pub hidden_asks: Vec<Order>,
+    /// Pending stop orders in arrival order
+    pub stops: Vec<StopOrder>,
+    /// Book sequence number when the snapshot was taken
+    pub sequence: u64,
+}
+
+/// A change to the book, in the order it happened, see
+/// `OrderBook::set_event_handler`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookEvent {
+    /// A lit order started resting
+    OrderAdded(Order),
+    /// A hidden order started resting, see `OrderBook::place_hidden`
+    HiddenOrderAdded(Order),
+    /// A resting order was removed without trading
+    OrderCancelled(Uuid),
+    TradeExecuted(Trade),
+    OrderAmended { id: Uuid, old_qty: Quantity, new_qty: Quantity },
+    /// Every order was removed and the book reset, see `OrderBook::clear`
+    Cleared,
+}